@@ -1,9 +1,11 @@
+/// Maps normalized device coordinates to pixels, flipping y so that row 0 is the top of the image.
+/// Depth stays in the [0,depth] range, with 0 at the near plane.
 pub fn viewport_matrix(x:f32,y:f32,width:f32,height:f32,depth:f32) -> glam::Mat4{
     let m = glam::Mat4::from_cols_array(&[
         width/2.0, 0.0, 0.0, 0.0,
-        0.0, height/2.0, 0.0, 0.0,
-        0.0, 0.0, depth/2.0, 0.0,
-        x+width/2.0, y+height/2.0, depth/2.0, 1.0,
+        0.0, -height/2.0, 0.0, 0.0,
+        0.0, 0.0, depth, 0.0,
+        x+width/2.0, y+height/2.0, 0.0, 1.0,
     ]);
     return m;
 }
@@ -18,9 +20,13 @@ pub struct Camera{
 
 impl Camera{
     pub fn new(width:u32,height:u32) -> Camera{
+        Camera::with_fov(width,height,60.0)
+    }
+
+    pub fn with_fov(width:u32,height:u32,fov:f32) -> Camera{
         let position = glam::Vec3::new(0.0,0.0,1.0);
         let view = glam::Mat4::look_at_rh(position,glam::Vec3::ZERO,glam::Vec3::new(0.0,1.0,0.0));
-        let projection = glam::Mat4::perspective_lh(f32::to_radians(60.0),
+        let projection = glam::Mat4::perspective_rh(f32::to_radians(fov),
             width as f32/height as f32,
             0.1,
             100.0);
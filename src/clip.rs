@@ -0,0 +1,171 @@
+use crate::shader::{VertOutput,lerp_vertoutput};
+
+/// The six planes of the view frustum in homogeneous clip space,
+/// using the [0,1] depth range of `Mat4::perspective_rh`.
+#[derive(Clone, Copy)]
+pub enum ClipPlane{
+    Near,
+    Far,
+    Left,
+    Right,
+    Bottom,
+    Top,
+}
+
+pub const CLIP_PLANES: [ClipPlane; 6] = [
+    ClipPlane::Near,
+    ClipPlane::Far,
+    ClipPlane::Left,
+    ClipPlane::Right,
+    ClipPlane::Bottom,
+    ClipPlane::Top,
+];
+
+impl ClipPlane{
+    /// Signed distance of a clip space position from the plane, positive when inside
+    pub fn distance(&self,p:&glam::Vec4) -> f32{
+        match self{
+            ClipPlane::Near => p.z,
+            ClipPlane::Far => p.w - p.z,
+            ClipPlane::Left => p.w + p.x,
+            ClipPlane::Right => p.w - p.x,
+            ClipPlane::Bottom => p.w + p.y,
+            ClipPlane::Top => p.w - p.y,
+        }
+    }
+}
+
+pub fn is_inside_frustum(p:&glam::Vec4) -> bool{
+    CLIP_PLANES.iter().all(|plane| plane.distance(p)>=0.0)
+}
+
+/// Sutherland-Hodgman clipping of a single polygon against one plane
+pub fn clip_against_plane(polygon:&[VertOutput],plane:ClipPlane) -> Vec<VertOutput>{
+    let mut output = Vec::with_capacity(polygon.len()+1);
+    if polygon.is_empty(){
+        return output;
+    }
+
+    let mut previous = &polygon[polygon.len()-1];
+    let mut previous_distance = plane.distance(&previous.position);
+    for current in polygon.iter(){
+        let current_distance = plane.distance(&current.position);
+        if current_distance>=0.0 {
+            if previous_distance<0.0 {
+                let t = previous_distance/(previous_distance-current_distance);
+                output.push(lerp_vertoutput(previous,current,t));
+            }
+            output.push(*current);
+        } else if previous_distance>=0.0 {
+            let t = previous_distance/(previous_distance-current_distance);
+            output.push(lerp_vertoutput(previous,current,t));
+        }
+        previous = current;
+        previous_distance = current_distance;
+    }
+    output
+}
+
+/// Clips a triangle in clip space against the view frustum, returning a convex polygon
+/// that is empty when the triangle is fully outside.
+pub fn clip_triangle(v0:&VertOutput,v1:&VertOutput,v2:&VertOutput) -> Vec<VertOutput>{
+    let mut polygon = vec![*v0,*v1,*v2];
+    if polygon.iter().all(|v| is_inside_frustum(&v.position)){
+        return polygon;
+    }
+    for plane in CLIP_PLANES{
+        //a triangle fully outside any plane can be rejected early
+        if polygon.iter().all(|v| plane.distance(&v.position)<0.0){
+            return vec![];
+        }
+        polygon = clip_against_plane(&polygon,plane);
+        if polygon.len()<3 {
+            return vec![];
+        }
+    }
+    polygon
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// A vertex whose attributes are affine functions of its clip space position, so any point
+    /// linearly interpolated between such vertices must still match them
+    fn vertex(position:glam::Vec4) -> VertOutput{
+        VertOutput{
+            position,
+            world_position: glam::Vec3::new(position.x*2.0+1.0,position.y-position.w,position.z*0.5),
+            uv: glam::Vec2::new(position.x+position.w,position.y*3.0-1.0),
+            normal: glam::Vec3::new(position.z,position.w,1.0),
+            tangent: glam::Vec3::new(1.0,position.x-position.z,0.0),
+            bitangent: glam::Vec3::new(position.y,0.0,position.w*0.25),
+            uv_dx: glam::Vec2::ZERO,
+            uv_dy: glam::Vec2::ZERO,
+        }
+    }
+
+    fn assert_inside_and_linear(polygon:&[VertOutput]){
+        for v in polygon{
+            for plane in CLIP_PLANES{
+                assert!(plane.distance(&v.position)>=-1e-5,"{:?} is outside of the clip volume",v.position);
+            }
+            let expected = vertex(v.position);
+            assert!(v.world_position.abs_diff_eq(expected.world_position,1e-5));
+            assert!(v.uv.abs_diff_eq(expected.uv,1e-5));
+            assert!(v.normal.abs_diff_eq(expected.normal,1e-5));
+            assert!(v.tangent.abs_diff_eq(expected.tangent,1e-5));
+            assert!(v.bitangent.abs_diff_eq(expected.bitangent,1e-5));
+        }
+    }
+
+    #[test]
+    fn inside_triangle_is_unchanged(){
+        let triangle = [
+            glam::Vec4::new(-0.5,-0.5,0.5,1.0),
+            glam::Vec4::new(0.5,-0.5,0.2,1.0),
+            glam::Vec4::new(0.0,1.5,1.0,2.0),
+        ];
+        let polygon = clip_triangle(&vertex(triangle[0]),&vertex(triangle[1]),&vertex(triangle[2]));
+        assert_eq!(polygon.iter().map(|v| v.position).collect::<Vec<_>>(),triangle.to_vec());
+        assert_inside_and_linear(&polygon);
+    }
+
+    #[test]
+    fn outside_triangle_is_rejected(){
+        let polygon = clip_triangle(
+            &vertex(glam::Vec4::new(2.0,0.0,0.5,1.0)),
+            &vertex(glam::Vec4::new(3.0,0.5,0.5,1.0)),
+            &vertex(glam::Vec4::new(2.5,-0.5,0.5,1.0)),
+        );
+        assert!(polygon.is_empty());
+    }
+
+    #[test]
+    fn near_plane_crossing_is_clipped(){
+        let polygon = clip_triangle(
+            &vertex(glam::Vec4::new(-0.5,0.0,0.5,1.0)),
+            &vertex(glam::Vec4::new(0.5,0.0,0.5,1.0)),
+            &vertex(glam::Vec4::new(0.0,0.5,-0.5,1.0)),
+        );
+        //one corner is cut off, leaving a quad
+        assert_eq!(polygon.len(),4);
+        assert!(polygon.iter().any(|v| v.position.z.abs()<1e-6));
+        assert_inside_and_linear(&polygon);
+    }
+
+    #[test]
+    fn vertex_behind_the_eye_is_clipped(){
+        let projection = glam::Mat4::perspective_rh(1.0,1.0,0.1,100.0);
+        let triangle = [
+            glam::Vec3::new(-1.0,0.0,-5.0),
+            glam::Vec3::new(1.0,0.0,-5.0),
+            glam::Vec3::new(0.0,0.5,2.0),
+        ].map(|p| projection*glam::Vec4::from((p,1.0)));
+        assert!(triangle[2].w<0.0);
+        let polygon = clip_triangle(&vertex(triangle[0]),&vertex(triangle[1]),&vertex(triangle[2]));
+        assert!(polygon.len()>=3);
+        assert!(polygon.iter().all(|v| v.position.w>0.0));
+        assert_inside_and_linear(&polygon);
+    }
+}
@@ -0,0 +1,64 @@
+use std::sync::OnceLock;
+
+/// The sRGB electro-optical transfer function, from encoded [0,1] values to linear light
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Decodes an 8 bit sRGB value, through a lookup table since textures and blending do it for every texel
+pub fn srgb_byte_to_linear(value: u8) -> f32 {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (i, v) in table.iter_mut().enumerate() {
+            *v = srgb_to_linear(i as f32 / 255.0);
+        }
+        table
+    });
+    table[value as usize]
+}
+
+/// Encodes a linear value to 8 bit sRGB, values outside of [0,1] are clamped
+pub fn linear_to_srgb_byte(value: f32) -> u8 {
+    (linear_to_srgb(value.clamp(0.0, 1.0)) * 255.0).round() as u8
+}
+
+/// Quantizes a linear value, such as alpha, to 8 bits without any transfer function
+pub fn linear_to_byte(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+pub fn byte_to_linear(value: u8) -> f32 {
+    value as f32 / 255.0
+}
+
+/// Encodes a linear color to 8 bit sRGB, as stored in color textures and images. Alpha stays linear.
+pub fn encode_color(color:&glam::Vec4) -> [u8;4]{
+    [
+        linear_to_srgb_byte(color.x),
+        linear_to_srgb_byte(color.y),
+        linear_to_srgb_byte(color.z),
+        linear_to_byte(color.w),
+    ]
+}
+
+pub fn decode_color(color:&[u8]) -> glam::Vec4{
+    glam::Vec4::new(
+        srgb_byte_to_linear(color[0]),
+        srgb_byte_to_linear(color[1]),
+        srgb_byte_to_linear(color[2]),
+        byte_to_linear(color[3]),
+    )
+}
@@ -1,38 +1,44 @@
-use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
-use winit::{window::Window};
 use std::mem;
-use glam::Vec3Swizzles;
 use glam::Vec4Swizzles;
+use rayon::prelude::*;
 use crate::model::{Model,Vertex,Material};
-use crate::shader::{Shader,interpolate_vertoutput,VertInput,GlobalData};
+use crate::shader::{Shader,VertInput,VertOutput,GlobalData};
+use crate::clip::clip_triangle;
+use crate::framebuffer::Framebuffer;
+use crate::present::FrameSink;
+use crate::tonemap::ToneMapping;
+use crate::environment::Environment;
+use crate::camera::Camera;
+use crate::render_state::RenderState;
+use crate::raster::{ScreenTriangle,bin_triangles,sample_pattern};
 
 
 pub struct Canvas {
     pub width: u32,
     pub height: u32,
-    pub pixels: Pixels,
-    pub depth_buffer: Vec<f32>,
+    pub framebuffer: Framebuffer,
+    pub render_state: RenderState,
+    pub thread_pool: rayon::ThreadPool,
+    pub tone_mapping: ToneMapping,
 }
 
-fn linear_to_byte(value: f32) -> u8 {
-    (value * 255.0) as u8
+fn build_thread_pool(threads:usize) -> Result<rayon::ThreadPool,String>{
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|e| format!("Failed to create the render threads: {}",e))
 }
 
-pub fn to_barycentric(a: &glam::Vec3, b: &glam::Vec3, c: &glam::Vec3, p: glam::Vec3) -> glam::Vec3 {
-    let s1 = glam::Vec3::new(c.x - a.x, b.x - a.x, a.x - p.x);
-    let s2 = glam::Vec3::new(c.y - a.y, b.y - a.y, a.y - p.y);
-
-    let u = s1.cross(s2);
-    if u.z.abs()>1e-2{
-        return glam::Vec3::new(
-            1.0 - (u.x + u.y) / u.z,
-            u.y / u.z,
-            u.x / u.z,
-        );
-    }else{
-        return glam::Vec3::new(-1.0, 1.0, 1.0);
+/// Signed area of a window space polygon, positive when it is counter clockwise on screen
+pub fn signed_area(polygon:&[VertOutput]) -> f32{
+    let mut area = 0.0;
+    for i in 0..polygon.len(){
+        let a = polygon[i].position;
+        let b = polygon[(i+1)%polygon.len()].position;
+        area += a.x*b.y - b.x*a.y;
     }
-
+    //window space has y pointing down, flip it so the winding matches what is seen on screen
+    -area*0.5
 }
 
 pub fn interpolate_bc<T>(a:T,b:T,c:T,barycentric:&glam::Vec3) -> T
@@ -45,62 +51,52 @@ pub fn interpolate_bc<T>(a:T,b:T,c:T,barycentric:&glam::Vec3) -> T
 
 impl Canvas {
 
-    pub fn new(width: u32, height: u32, window:&Window) -> Result<Canvas, String> {
-        let window_size = window.inner_size();
-        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height,&window);
-        let depth_buffer = vec![f32::NEG_INFINITY; (width * height) as usize];
-
-        if let Ok(pixels) = 
-            PixelsBuilder::new(width,height,surface_texture).build()
-        {
-            Ok(Canvas{
-                width,
-                height,
-                pixels,
-                depth_buffer,
-            })
-        } else {
-            return Err("Failed to initialize frame buffer".to_string());
+    pub fn new(width: u32, height: u32) -> Canvas {
+        Canvas{
+            width,
+            height,
+            framebuffer: Framebuffer::new(width,height,1),
+            render_state: RenderState::default(),
+            thread_pool: build_thread_pool(0).expect("There was an error creating the render threads"),
+            tone_mapping: ToneMapping::default(),
         }
     }
 
-    pub fn render(&self){
-        self.pixels.render().unwrap();
+    /// Sets the number of samples per pixel used for anti-aliasing (1, 2, 4 or 8), clearing the framebuffer
+    pub fn set_sample_count(&mut self,samples:u32) -> Result<(),String>{
+        sample_pattern(samples)?;
+        self.framebuffer = Framebuffer::new(self.width,self.height,samples);
+        Ok(())
+    }
+
+    /// Sets how many threads are used to rasterize, 0 picks one per logical core.
+    /// The image is the same regardless of the thread count.
+    pub fn set_thread_count(&mut self,threads:usize) -> Result<(),String>{
+        self.thread_pool = build_thread_pool(threads)?;
+        Ok(())
+    }
+
+    pub fn present(&self,sink:&mut dyn FrameSink) -> Result<(),String>{
+        sink.present(&self.framebuffer,&self.tone_mapping)
     }
     
     
     pub fn set_pixel(&mut self,x:i32,y:i32,color:&glam::Vec4){
-        let frame = self.pixels.get_frame(); 
-        if x>=self.width as i32 || y>=self.height as i32 || x<0 || y<0 {
-            return;
-        }
-        let index = ((y as u32*self.width+x as u32)*4) as usize;
-        frame[index] = linear_to_byte(color.x);
-        frame[index+1] = linear_to_byte(color.y);
-        frame[index+2] = linear_to_byte(color.z);
-        frame[index+3] = linear_to_byte(color.w);
+        self.framebuffer.set_pixel(x,y,color);
     }
 
     pub fn set_pixel_depth(&mut self,x:i32,y:i32,depth:f32){
-        if x>=self.width as i32 || y>=self.height as i32 || x<0 || y<0 {
-            return;
-        }
-        let index = (y as u32*self.width+x as u32) as usize;
-        self.depth_buffer[index] = depth;
+        self.framebuffer.set_depth(x,y,depth);
     }
 
     pub fn get_pixel_depth(&self,x:i32,y:i32) -> f32{
-        if x>=self.width as i32 || y>=self.height as i32 || x<0 || y<0 {
-            return f32::INFINITY;
-        }
-        let index = (y as u32*self.width+x as u32) as usize;
-        self.depth_buffer[index]
+        self.framebuffer.get_depth(x,y)
     }
     
     pub fn clear_frame(&mut self){
-        let frame = self.pixels.get_frame();
-        frame.iter_mut().for_each(|x| *x=128);
-        self.depth_buffer.iter_mut().for_each(|x| *x=f32::NEG_INFINITY);
+        //the gray background of the 8 bit framebuffer (128 in sRGB), brought back through the tone mapping
+        let background = self.tone_mapping.unmap(glam::Vec4::new(0.2158,0.2158,0.2158,1.0));
+        self.framebuffer.clear(background,f32::INFINITY);
     }
     
     pub fn draw_line(&mut self,x0:i32,y0:i32,x1:i32,y1:i32,color:&glam::Vec4){
@@ -146,7 +142,7 @@ impl Canvas {
     pub fn to_screen_space(&self,v:&glam::Vec3) -> glam::Vec3{
         glam::Vec3::new(
             (v.x+1.0)*self.width as f32/2.0,
-            (v.y*-1.0+1.0)*self.height as f32/2.0,
+            (1.0-v.y)*self.height as f32/2.0,
             v.z
         )
     }
@@ -158,53 +154,77 @@ impl Canvas {
     }
     
 
-    pub fn draw_triangle(&mut self, v0:&Vertex,v1:&Vertex,v2:&Vertex,shader:&dyn Shader,material:&Material,vert_input:&VertInput,globals:&GlobalData, is_wireframe:bool){ 
-        let t0 = shader.vertex(v0,&vert_input,globals);
-        let t1 = shader.vertex(v1,&vert_input,globals);
-        let t2 = shader.vertex(v2,&vert_input,globals);
+    /// Perspective divide and viewport transform, the resulting position is (x,y) in pixels,
+    /// z in [0,1] and 1/w of the clip space position.
+    pub fn to_window_space(&self,v:&VertOutput,globals:&GlobalData) -> VertOutput{
+        let inv_w = 1.0/v.position.w;
+        let ndc = v.position.xyz()*inv_w;
+        let screen = globals.camera.viewport.transform_point3(ndc);
+        VertOutput{
+            position: glam::Vec4::new(screen.x,screen.y,screen.z,inv_w),
+            ..*v
+        }
+    }
 
-        if is_wireframe {
-            self.draw_wire_triangle(
-                t0.position.xy(),
-                t1.position.xy(),
-                t2.position.xy(),
-                &glam::Vec4::ONE,
-            );
-            return;
+    /// Runs the vertex shader on a triangle, then clips and culls it.
+    /// Returns the visible part as a convex polygon in window space.
+    pub fn process_triangle(&self, v0:&Vertex,v1:&Vertex,v2:&Vertex,shader:&dyn Shader,vert_input:&VertInput,globals:&GlobalData) -> Option<Vec<VertOutput>>{
+        let t0 = shader.vertex(v0,vert_input,globals);
+        let t1 = shader.vertex(v1,vert_input,globals);
+        let t2 = shader.vertex(v2,vert_input,globals);
+
+        let polygon = clip_triangle(&t0,&t1,&t2);
+        if polygon.is_empty(){
+            return None;
+        }
+        let polygon:Vec<VertOutput> = polygon.iter().map(|v| self.to_window_space(v,globals)).collect();
+        if self.render_state.is_culled(signed_area(&polygon)){
+            return None;
         }
+        Some(polygon)
+    }
 
-        let mut max_box = glam::Vec2::new(0.0,0.0);
-        let mut min_box = glam::Vec2::new((self.width-1) as f32,(self.height-1) as f32);
-        let clamp = min_box.clone();
-        for v in [&t0,&t1,&t2] {
-            max_box.x = max_box.x.max(v.position.x).min(clamp.x);
-            max_box.y = max_box.y.max(v.position.y).min(clamp.y);
+    /// Splits a window space polygon into triangles that cover at least one pixel
+    pub fn triangulate<'a>(&self,polygon:&[VertOutput],material:&'a Material) -> Vec<ScreenTriangle<'a>>{
+        (1..polygon.len()-1)
+            .filter_map(|i| ScreenTriangle::new([polygon[0],polygon[i],polygon[i+1]],material,self.width,self.height))
+            .collect()
+    }
 
-            min_box.x = min_box.x.min(v.position.x).max(0.0);
-            min_box.y = min_box.y.min(v.position.y).max(0.0);
+    pub fn draw_wire_polygon(&mut self,polygon:&[VertOutput],color:&glam::Vec4){
+        for i in 0..polygon.len(){
+            let next = (i+1)%polygon.len();
+            self.draw_line_vec(&polygon[i].position.xy(),&polygon[next].position.xy(),color);
         }
+    }
 
-        let mut x = min_box.x.ceil() as i32;
-        while x<max_box.x.ceil() as i32{
-            let mut y = min_box.y.ceil() as i32;
-            while y<max_box.y.ceil() as i32{
-                let bc = to_barycentric(&t0.position,&t1.position,&t2.position,glam::Vec3::new(x as f32,y as f32, 0.0));
-                if bc.x>=0.0 && bc.y>=0.0 && bc.z>=0.0 {
-                    let z = bc.x*t0.position.z + bc.y*t1.position.z + bc.z*t2.position.z;
-                    if z>self.get_pixel_depth(x, y){
-                        let input = interpolate_vertoutput(&t0,&t1,&t2,&bc);
-                        let color = shader.fragment(&input,material,globals);
-                        self.set_pixel(x,y,&color);
-                        self.set_pixel_depth(x,y,z);
-                    }
-                }
-                y+=1;
+    pub fn draw_triangle(&mut self, v0:&Vertex,v1:&Vertex,v2:&Vertex,shader:&dyn Shader,material:&Material,vert_input:&VertInput,globals:&GlobalData, is_wireframe:bool){ 
+        if let Some(polygon) = self.process_triangle(v0,v1,v2,shader,vert_input,globals){
+            if is_wireframe {
+                self.draw_wire_polygon(&polygon,&glam::Vec4::ONE);
+            } else {
+                let triangles = self.triangulate(&polygon,material);
+                self.draw_triangles(&triangles,shader,globals);
             }
-            x+=1;
         }
-        
     }
 
+    /// Rasterizes window space triangles. The screen is split in tiles that are shaded in parallel,
+    /// every tile draws its triangles in submission order so the result is deterministic.
+    pub fn draw_triangles(&mut self,triangles:&[ScreenTriangle],shader:&dyn Shader,globals:&GlobalData){
+        let mut tiles = bin_triangles(triangles,&self.framebuffer);
+        let state = self.render_state;
+        self.thread_pool.install(|| {
+            tiles.par_iter_mut().for_each(|tile| {
+                for i in 0..tile.triangles.len(){
+                    tile.rasterize(&triangles[tile.triangles[i]],shader,globals,&state);
+                }
+            });
+        });
+        for tile in tiles.iter(){
+            self.framebuffer.write_region(&tile.buffer,tile.x,tile.y);
+        }
+    }
 
     pub fn draw_debug(&mut self, model:&Model, vert_input:&VertInput,_globals:&GlobalData){
         let mut o = glam::Vec4::new(0.0,0.0,0.0,1.0);
@@ -225,7 +245,7 @@ impl Canvas {
             p /= p.w;
             self.set_pixel(p.x as i32,p.y as i32,&glam::Vec4::new(1.0,0.0,0.0,1.0));
 
-            for n in [v.normal,v.tangent,v.bitangent]{
+            for n in [v.normal,v.tangent.truncate(),v.bitangent()]{
                 let mut end = vert_input.mvpv*glam::Vec4::from((v.position+n.normalize()*scale,1.0));
                 end /= end.w;
                 self.draw_line_vec(&p.xy(),&end.xy(),&glam::Vec4::from((n,1.0)));
@@ -233,40 +253,126 @@ impl Canvas {
         }
     }
 
-    pub fn draw_model(&mut self,model:&Model,shader:&dyn Shader,globals:&GlobalData,is_wireframe:bool,is_debug:bool){
+    pub fn model_vert_input(&self,globals:&GlobalData) -> VertInput{
         let model_matrix = glam::Mat4::IDENTITY;
         let model_inverse_transpose = model_matrix.inverse().transpose();
         let mv = globals.camera.view*model_matrix;
         let mvp = globals.camera.projection*mv;
         let mvpv = globals.camera.viewport*mvp;
 
-        let v_in = VertInput
+        VertInput
         {
             mvpv:mvpv,
             mvp: mvp,
             mv: mv,
             m:model_matrix,
             mit:model_inverse_transpose,
-        };
-
-        
-        for face in model.faces.iter(){
-            self.draw_triangle(
-                &model.vertices[face.vertices[0]],
-                &model.vertices[face.vertices[1]],
-                &model.vertices[face.vertices[2]],
-                shader,
-                &model.material,
-                &v_in,
-                globals,
-                is_wireframe,
-            );
         }
+    }
+
+    /// Runs the geometry stage on the faces of a model whose material passes `filter`, in parallel,
+    /// keeping the face order. Every polygon comes with the material of its face.
+    pub fn process_model<'a>(&self,model:&'a Model,filter:&(dyn Fn(&Material) -> bool + Sync),shader:&dyn Shader,v_in:&VertInput,globals:&GlobalData) -> Vec<(&'a Material,Vec<VertOutput>)>{
+        self.thread_pool.install(|| {
+            model.faces.par_iter().filter(|face| filter(model.material(face))).filter_map(|face| {
+                let polygon = self.process_triangle(
+                    &model.vertices[face.vertices[0]],
+                    &model.vertices[face.vertices[1]],
+                    &model.vertices[face.vertices[2]],
+                    shader,
+                    v_in,
+                    globals,
+                )?;
+                Some((model.material(face),polygon))
+            }).collect()
+        })
+    }
+
+    /// Draws the faces of a model whose material passes `filter`, in face order
+    pub fn draw_model_faces(&mut self,model:&Model,filter:&(dyn Fn(&Material) -> bool + Sync),shader:&dyn Shader,globals:&GlobalData,is_wireframe:bool){
+        let v_in = self.model_vert_input(globals);
+        let polygons = self.process_model(model,filter,shader,&v_in,globals);
+
+        if is_wireframe {
+            for (_,polygon) in polygons.iter(){
+                self.draw_wire_polygon(polygon,&glam::Vec4::ONE);
+            }
+        } else {
+            let triangles:Vec<ScreenTriangle> = polygons.iter()
+                .flat_map(|(material,polygon)| self.triangulate(polygon,material))
+                .collect();
+            self.draw_triangles(&triangles,shader,globals);
+        }
+    }
+
+    pub fn draw_model(&mut self,model:&Model,shader:&dyn Shader,globals:&GlobalData,is_wireframe:bool,is_debug:bool){
+        self.draw_model_faces(model,&|_| true,shader,globals,is_wireframe);
 
         if is_debug{
+            let v_in = self.model_vert_input(globals);
             self.draw_debug(model, &v_in,globals);
         }
     }
 
+    /// Fills the whole framebuffer with the environment seen through the camera, leaving the depth untouched
+    pub fn draw_environment(&mut self,environment:&Environment,camera:&Camera){
+        let inverse = (camera.viewport*camera.projection*camera.view).inverse();
+        let width = self.width;
+        let samples = self.framebuffer.samples as usize;
+        self.thread_pool.install(|| {
+            self.framebuffer.color.par_chunks_mut(width as usize*samples*4).enumerate().for_each(|(y,row)| {
+                for (x,pixel) in row.chunks_exact_mut(samples*4).enumerate(){
+                    let near = inverse.project_point3(glam::Vec3::new(x as f32+0.5,y as f32+0.5,0.0));
+                    let far = inverse.project_point3(glam::Vec3::new(x as f32+0.5,y as f32+0.5,1.0));
+                    let color = glam::Vec4::from((environment.background(far-near),1.0));
+                    for sample in pixel.chunks_exact_mut(4){
+                        sample.copy_from_slice(&color.to_array());
+                    }
+                }
+            });
+        });
+    }
+
+    /// Draws the environment as background if there is one, the opaque faces of every model, then the
+    /// transparent triangles of every model sorted back to front, with depth writes disabled.
+    pub fn draw_scene(&mut self,models:&[Model],shader:&dyn Shader,globals:&GlobalData,is_wireframe:bool,is_debug:bool){
+        if let Some(environment) = &globals.environment {
+            self.draw_environment(environment,&globals.camera);
+        }
+        if is_wireframe {
+            for model in models.iter(){
+                self.draw_model(model,shader,globals,is_wireframe,is_debug);
+            }
+            return;
+        }
+
+        for model in models.iter(){
+            self.draw_model_faces(model,&|material| !material.blend_mode.is_transparent(),shader,globals,false);
+        }
+
+        let v_in = self.model_vert_input(globals);
+        let mut sorted = Vec::<(f32,ScreenTriangle)>::new();
+        for model in models.iter(){
+            for (material,polygon) in self.process_model(model,&|material| material.blend_mode.is_transparent(),shader,&v_in,globals).iter(){
+                //w of a window space position is 1/w, the distance along the view direction
+                let depth = polygon.iter().map(|v| 1.0/v.position.w).sum::<f32>()/polygon.len() as f32;
+                for triangle in self.triangulate(polygon,material){
+                    sorted.push((depth,triangle));
+                }
+            }
+        }
+        sorted.sort_by(|a,b| b.0.total_cmp(&a.0));
+        let triangles:Vec<ScreenTriangle> = sorted.into_iter().map(|(_,triangle)| triangle).collect();
+
+        let depth_write = self.render_state.depth_write;
+        self.render_state.depth_write = false;
+        self.draw_triangles(&triangles,shader,globals);
+        self.render_state.depth_write = depth_write;
 
+        if is_debug{
+            for model in models.iter(){
+                self.draw_debug(model,&v_in,globals);
+            }
+        }
+    }
 }
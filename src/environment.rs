@@ -0,0 +1,186 @@
+use rayon::prelude::*;
+use std::f32::consts::PI;
+
+/// Roughness of each prefiltered specular level, level 0 is the environment itself
+const SPECULAR_ROUGHNESS: [f32; 6] = [0.0, 0.2, 0.4, 0.6, 0.8, 1.0];
+const PREFILTER_WIDTH: u32 = 64;
+const PREFILTER_HEIGHT: u32 = 32;
+
+/// A linear equirectangular image
+pub struct EnvironmentImage{
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<glam::Vec3>,
+}
+
+/// Unit direction of the center of an equirectangular texel coordinate, u goes around +y and v from top to bottom
+fn direction_from_uv(u:f32,v:f32) -> glam::Vec3{
+    let phi = (u-0.5)*2.0*PI;
+    let theta = v*PI;
+    glam::Vec3::new(theta.sin()*phi.sin(),theta.cos(),-theta.sin()*phi.cos())
+}
+
+fn uv_from_direction(direction:glam::Vec3) -> glam::Vec2{
+    let d = direction.normalize();
+    glam::Vec2::new(0.5+d.x.atan2(-d.z)/(2.0*PI),d.y.clamp(-1.0,1.0).acos()/PI)
+}
+
+impl EnvironmentImage{
+    fn texel(&self,x:i32,y:i32) -> glam::Vec3{
+        //wrap around the horizon, clamp at the poles
+        let x = x.rem_euclid(self.width as i32) as u32;
+        let y = y.clamp(0,self.height as i32-1) as u32;
+        self.pixels[(y*self.width+x) as usize]
+    }
+
+    /// Bilinear lookup of the radiance coming from a direction
+    pub fn sample(&self,direction:glam::Vec3) -> glam::Vec3{
+        let uv = uv_from_direction(direction);
+        let st = uv*glam::Vec2::new(self.width as f32,self.height as f32)-0.5;
+        let base = st.floor();
+        let f = st-base;
+        let (x,y) = (base.x as i32,base.y as i32);
+        let top = self.texel(x,y).lerp(self.texel(x+1,y),f.x);
+        let bottom = self.texel(x,y+1).lerp(self.texel(x+1,y+1),f.x);
+        top.lerp(bottom,f.y)
+    }
+
+    /// Box filtered copy with the given size
+    fn resize(&self,width:u32,height:u32) -> EnvironmentImage{
+        let mut pixels = Vec::with_capacity((width*height) as usize);
+        for y in 0..height{
+            let y0 = y*self.height/height;
+            let y1 = ((y+1)*self.height/height).max(y0+1);
+            for x in 0..width{
+                let x0 = x*self.width/width;
+                let x1 = ((x+1)*self.width/width).max(x0+1);
+                let mut sum = glam::Vec3::ZERO;
+                for sy in y0..y1{
+                    for sx in x0..x1{
+                        sum += self.pixels[(sy*self.width+sx) as usize];
+                    }
+                }
+                pixels.push(sum/((x1-x0)*(y1-y0)) as f32);
+            }
+        }
+        EnvironmentImage{width,height,pixels}
+    }
+
+    /// Direction and solid angle of every texel
+    fn texel_directions(&self) -> Vec<(glam::Vec3,f32)>{
+        let texel_angle = (2.0*PI/self.width as f32)*(PI/self.height as f32);
+        (0..self.height).flat_map(|y| (0..self.width).map(move |x| (x,y))).map(|(x,y)| {
+            let u = (x as f32+0.5)/self.width as f32;
+            let v = (y as f32+0.5)/self.height as f32;
+            (direction_from_uv(u,v),texel_angle*(v*PI).sin())
+        }).collect()
+    }
+}
+
+/// The first nine real spherical harmonics, irradiance from them follows Ramamoorthi and Hanrahan
+fn sh_basis(d:glam::Vec3) -> [f32; 9]{
+    [
+        0.282095,
+        0.488603*d.y,
+        0.488603*d.z,
+        0.488603*d.x,
+        1.092548*d.x*d.y,
+        1.092548*d.y*d.z,
+        0.315392*(3.0*d.z*d.z-1.0),
+        1.092548*d.x*d.z,
+        0.546274*(d.x*d.x-d.y*d.y),
+    ]
+}
+
+/// Distant lighting from an environment map, with the convolutions needed by the shaders done at load time
+pub struct Environment{
+    pub image: EnvironmentImage,
+    /// Radiance projected on the first three bands of spherical harmonics
+    pub irradiance_sh: [glam::Vec3; 9],
+    /// Environment convolved with GGX lobes of increasing roughness, see SPECULAR_ROUGHNESS
+    pub specular: Vec<EnvironmentImage>,
+}
+
+impl Environment{
+    pub fn load(path:&str) -> Result<Environment,String>{
+        println!("Loading environment from {}", path);
+        let image = image::open(path).map_err(|e| format!("Failed to load environment {}: {}",path,e))?;
+        let width = image.width();
+        let height = image.height();
+        let pixels = image.into_rgb32f().pixels().map(|p| glam::Vec3::new(p[0],p[1],p[2])).collect();
+        Ok(Environment::new(EnvironmentImage{width,height,pixels}))
+    }
+
+    pub fn new(image:EnvironmentImage) -> Environment{
+        let small = image.resize(PREFILTER_WIDTH,PREFILTER_HEIGHT);
+        let texels = small.texel_directions();
+
+        let mut irradiance_sh = [glam::Vec3::ZERO; 9];
+        for ((direction,solid_angle),radiance) in texels.iter().zip(small.pixels.iter()){
+            for (coefficient,basis) in irradiance_sh.iter_mut().zip(sh_basis(*direction)){
+                *coefficient += *radiance*basis*(*solid_angle);
+            }
+        }
+        //convolution with the clamped cosine lobe, per band
+        let bands = [PI, 2.0*PI/3.0, 2.0*PI/3.0, 2.0*PI/3.0, PI/4.0, PI/4.0, PI/4.0, PI/4.0, PI/4.0];
+        for (coefficient,band) in irradiance_sh.iter_mut().zip(bands){
+            *coefficient *= band;
+        }
+
+        let specular = SPECULAR_ROUGHNESS[1..].iter().map(|roughness| {
+            let alpha2 = (roughness*roughness).powi(2);
+            let pixels = texels.par_iter().map(|(normal,_)| {
+                //assumes the view direction is the normal, like most real time prefiltering
+                let mut sum = glam::Vec3::ZERO;
+                let mut weight = 0.0;
+                for ((direction,solid_angle),radiance) in texels.iter().zip(small.pixels.iter()){
+                    let cos = normal.dot(*direction);
+                    if cos<=0.0 {
+                        continue;
+                    }
+                    let d = cos*cos*(alpha2-1.0)+1.0;
+                    let w = alpha2/(d*d)*cos*solid_angle;
+                    sum += *radiance*w;
+                    weight += w;
+                }
+                if weight>0.0 {sum/weight} else {glam::Vec3::ZERO}
+            }).collect();
+            EnvironmentImage{width:small.width,height:small.height,pixels}
+        }).collect();
+
+        Environment{image,irradiance_sh,specular}
+    }
+
+    /// Irradiance arriving at a surface with the given normal
+    pub fn irradiance(&self,normal:glam::Vec3) -> glam::Vec3{
+        let basis = sh_basis(normal.normalize());
+        let mut irradiance = glam::Vec3::ZERO;
+        for (coefficient,basis) in self.irradiance_sh.iter().zip(basis){
+            irradiance += *coefficient*basis;
+        }
+        irradiance.max(glam::Vec3::ZERO)
+    }
+
+    /// Radiance reflected along a direction by a surface of the given roughness, before the BRDF scale and bias
+    pub fn prefiltered(&self,direction:glam::Vec3,roughness:f32) -> glam::Vec3{
+        let level = roughness.clamp(0.0,1.0)*(SPECULAR_ROUGHNESS.len()-1) as f32;
+        let index = (level.floor() as usize).min(SPECULAR_ROUGHNESS.len()-2);
+        let t = level-index as f32;
+        let sample = |i:usize| if i==0 {self.image.sample(direction)} else {self.specular[i-1].sample(direction)};
+        sample(index).lerp(sample(index+1),t)
+    }
+
+    /// Radiance seen in a direction, used for the background
+    pub fn background(&self,direction:glam::Vec3) -> glam::Vec3{
+        self.image.sample(direction)
+    }
+}
+
+/// Analytic fit of the split sum environment BRDF by Karis, returns the scale and bias applied to F0
+pub fn environment_brdf(n_dot_v:f32,roughness:f32) -> (f32,f32){
+    let c0 = glam::Vec4::new(-1.0,-0.0275,-0.572,0.022);
+    let c1 = glam::Vec4::new(1.0,0.0425,1.04,-0.04);
+    let r = c0*roughness+c1;
+    let a004 = (r.x*r.x).min((-9.28*n_dot_v).exp2())*r.x+r.y;
+    (a004*-1.04+r.z,a004*1.04+r.w)
+}
@@ -0,0 +1,131 @@
+/// Color and depth storage. Colors are linear RGBA32F without any upper bound, they are tone mapped
+/// when presented. With multisampling every pixel holds `samples` colors and depths, stored next to
+/// each other, which are averaged by `resolve`.
+pub struct Framebuffer{
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub color: Vec<f32>,
+    pub depth: Vec<f32>,
+}
+
+impl Framebuffer{
+    pub fn new(width: u32, height: u32, samples: u32) -> Framebuffer {
+        Framebuffer{
+            width,
+            height,
+            samples,
+            color: vec![0.0; (width * height * samples * 4) as usize],
+            depth: vec![f32::INFINITY; (width * height * samples) as usize],
+        }
+    }
+
+    /// Index of the first sample of a pixel
+    fn index(&self,x:i32,y:i32) -> Option<usize>{
+        if x>=self.width as i32 || y>=self.height as i32 || x<0 || y<0 {
+            return None;
+        }
+        Some(((y as u32*self.width+x as u32)*self.samples) as usize)
+    }
+
+    /// Writes the same color to every sample of a pixel
+    pub fn set_pixel(&mut self,x:i32,y:i32,color:&glam::Vec4){
+        if let Some(index) = self.index(x,y){
+            for sample in index..index+self.samples as usize{
+                self.color[sample*4..sample*4+4].copy_from_slice(&color.to_array());
+            }
+        }
+    }
+
+    pub fn set_sample(&mut self,x:i32,y:i32,sample:u32,color:&glam::Vec4){
+        if let Some(index) = self.index(x,y){
+            let index = (index+sample as usize)*4;
+            self.color[index..index+4].copy_from_slice(&color.to_array());
+        }
+    }
+
+    pub fn get_sample(&self,x:i32,y:i32,sample:u32) -> glam::Vec4{
+        match self.index(x,y){
+            Some(index) => {
+                let index = (index+sample as usize)*4;
+                glam::Vec4::from_slice(&self.color[index..index+4])
+            },
+            None => glam::Vec4::ZERO,
+        }
+    }
+
+    /// The average of the samples of a pixel
+    pub fn get_pixel(&self,x:i32,y:i32) -> glam::Vec4{
+        let mut color = glam::Vec4::ZERO;
+        for sample in 0..self.samples{
+            color += self.get_sample(x,y,sample);
+        }
+        color/self.samples as f32
+    }
+
+    pub fn set_sample_depth(&mut self,x:i32,y:i32,sample:u32,depth:f32){
+        if let Some(index) = self.index(x,y){
+            self.depth[index+sample as usize] = depth;
+        }
+    }
+
+    pub fn get_sample_depth(&self,x:i32,y:i32,sample:u32) -> f32{
+        match self.index(x,y){
+            Some(index) => self.depth[index+sample as usize],
+            None => f32::NEG_INFINITY,
+        }
+    }
+
+    /// Writes the same depth to every sample of a pixel
+    pub fn set_depth(&mut self,x:i32,y:i32,depth:f32){
+        if let Some(index) = self.index(x,y){
+            self.depth[index..index+self.samples as usize].iter_mut().for_each(|d| *d=depth);
+        }
+    }
+
+    /// The depth of the first sample of a pixel
+    pub fn get_depth(&self,x:i32,y:i32) -> f32{
+        self.get_sample_depth(x,y,0)
+    }
+
+    pub fn clear(&mut self,color:glam::Vec4,depth:f32){
+        self.color.chunks_exact_mut(4).for_each(|p| p.copy_from_slice(&color.to_array()));
+        self.depth.iter_mut().for_each(|d| *d=depth);
+    }
+
+    /// Averages the samples of every pixel into a single linear RGBA32F image
+    pub fn resolve(&self) -> Vec<f32>{
+        if self.samples==1 {
+            return self.color.clone();
+        }
+        let samples = self.samples as usize;
+        self.color.chunks_exact(samples*4).flat_map(|pixel| {
+            let sum = pixel.chunks_exact(4).map(glam::Vec4::from_slice).fold(glam::Vec4::ZERO,|sum,c| sum+c);
+            (sum/samples as f32).to_array()
+        }).collect()
+    }
+
+    /// Copies a rectangle of this framebuffer into a new, smaller one
+    pub fn region(&self,x:u32,y:u32,width:u32,height:u32) -> Framebuffer{
+        let mut region = Framebuffer::new(width,height,self.samples);
+        let row_len = (width*self.samples) as usize;
+        for row in 0..height{
+            let src = (((y+row)*self.width+x)*self.samples) as usize;
+            let dst = row as usize*row_len;
+            region.color[dst*4..(dst+row_len)*4].copy_from_slice(&self.color[src*4..(src+row_len)*4]);
+            region.depth[dst..dst+row_len].copy_from_slice(&self.depth[src..src+row_len]);
+        }
+        region
+    }
+
+    /// Copies a framebuffer obtained with `region` back in place
+    pub fn write_region(&mut self,region:&Framebuffer,x:u32,y:u32){
+        let row_len = (region.width*self.samples) as usize;
+        for row in 0..region.height{
+            let src = row as usize*row_len;
+            let dst = (((y+row)*self.width+x)*self.samples) as usize;
+            self.color[dst*4..(dst+row_len)*4].copy_from_slice(&region.color[src*4..(src+row_len)*4]);
+            self.depth[dst..dst+row_len].copy_from_slice(&region.depth[src..src+row_len]);
+        }
+    }
+}
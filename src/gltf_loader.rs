@@ -0,0 +1,268 @@
+use crate::model::{Model,Material,Vertex,LoadError,LoadedModels,LoadOptions,build_faces,generate_normals,generate_tangents};
+use crate::texture::{Texture,TextureBinding,ColorSpace,Sampler,FilterMode,WrapMode};
+use crate::texture_cache::{TextureCache,TextureKey};
+use crate::render_state::BlendMode;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Expands a decoded glTF image to RGBA8. Luminance-alpha images are stored as two channels,
+/// 16 bit channels keep their high byte and float channels are clamped to [0,1].
+fn to_rgba8(image:&gltf::image::Data) -> Vec<u8>{
+    use gltf::image::Format;
+    let channels = match image.format{
+        Format::R8 | Format::R16 => 1,
+        Format::R8G8 | Format::R16G16 => 2,
+        Format::R8G8B8 | Format::R16G16B16 | Format::R32G32B32FLOAT => 3,
+        Format::R8G8B8A8 | Format::R16G16B16A16 | Format::R32G32B32A32FLOAT => 4,
+    };
+    let values:Vec<u8> = match image.format{
+        Format::R8 | Format::R8G8 | Format::R8G8B8 | Format::R8G8B8A8 => image.pixels.clone(),
+        Format::R16 | Format::R16G16 | Format::R16G16B16 | Format::R16G16B16A16 => image.pixels.chunks_exact(2)
+            .map(|c| (u16::from_ne_bytes([c[0],c[1]])>>8) as u8).collect(),
+        Format::R32G32B32FLOAT | Format::R32G32B32A32FLOAT => image.pixels.chunks_exact(4)
+            .map(|c| crate::color::linear_to_byte(f32::from_ne_bytes([c[0],c[1],c[2],c[3]]))).collect(),
+    };
+    values.chunks_exact(channels).flat_map(|p| match p{
+        [l] => [*l,*l,*l,255],
+        [l,a] => [*l,*l,*l,*a],
+        [r,g,b] => [*r,*g,*b,255],
+        [r,g,b,a] => [*r,*g,*b,*a],
+        _ => unreachable!(),
+    }).collect()
+}
+
+fn wrap_mode(mode:gltf::texture::WrappingMode) -> WrapMode{
+    match mode{
+        gltf::texture::WrappingMode::Repeat => WrapMode::Repeat,
+        gltf::texture::WrappingMode::MirroredRepeat => WrapMode::MirroredRepeat,
+        gltf::texture::WrappingMode::ClampToEdge => WrapMode::ClampToEdge,
+    }
+}
+
+fn sampler(texture:&gltf::Texture) -> Sampler{
+    use gltf::texture::MinFilter;
+    let sampler = texture.sampler();
+    let filter = match sampler.min_filter(){
+        Some(MinFilter::Nearest) | Some(MinFilter::NearestMipmapNearest) => FilterMode::Nearest,
+        Some(MinFilter::Linear) | Some(MinFilter::LinearMipmapNearest) => FilterMode::Bilinear,
+        _ => FilterMode::Trilinear,
+    };
+    Sampler{
+        filter,
+        wrap_u: wrap_mode(sampler.wrap_s()),
+        wrap_v: wrap_mode(sampler.wrap_t()),
+        ..Sampler::default()
+    }
+}
+
+/// The buffers and images of a file, with the warnings found so far
+struct GltfData<'a>{
+    path: String,
+    buffers: Vec<gltf::buffer::Data>,
+    images: Vec<gltf::image::Data>,
+    options: LoadOptions,
+    /// Materials already loaded, by index in the file. None is the default material of glTF.
+    materials: HashMap<Option<usize>,Arc<Material>>,
+    cache: &'a mut TextureCache,
+    warnings: Vec<LoadError>,
+}
+
+impl GltfData<'_>{
+    fn warn(&mut self,message:String){
+        self.warnings.push(LoadError::Parse{path:self.path.clone(),message});
+    }
+
+    /// Builds a texture from the image of a glTF texture, through the cache.
+    /// Textures read with another set of texture coordinates than the first one are skipped.
+    fn load_texture(&mut self,texture:&gltf::Texture,tex_coord:u32,color_space:ColorSpace) -> Option<TextureBinding>{
+        if tex_coord!=0 {
+            self.warn(format!("Texture {} uses TEXCOORD_{}, only TEXCOORD_0 is supported",texture.index(),tex_coord));
+            return None;
+        }
+        let index = texture.source().index();
+        let image = &self.images[index];
+        let loaded = TextureKey::new(std::path::Path::new(&self.path),Some(index),color_space)
+            .and_then(|key| self.cache.get_or_load(key,|| Ok(Texture::new(image.width,image.height,to_rgba8(image)))));
+        match loaded{
+            Ok(loaded) => Some(TextureBinding{texture:loaded,sampler:sampler(texture),channel:None}),
+            Err(e) => {
+                self.warn(format!("Image {}: {}",index,e));
+                None
+            },
+        }
+    }
+}
+
+fn load_material(gltf_material:&gltf::Material,data:&mut GltfData) -> Material{
+    let mut material = Material::default();
+    let pbr = gltf_material.pbr_metallic_roughness();
+
+    material.albedo = glam::Vec4::from(pbr.base_color_factor());
+    if let Some(info) = pbr.base_color_texture(){
+        if let Some(binding) = data.load_texture(&info.texture(),info.tex_coord(),ColorSpace::Srgb){
+            material.albedo_texture = binding;
+        }
+    }
+
+    material.metallic = pbr.metallic_factor();
+    material.roughness = pbr.roughness_factor();
+    //roughness is in the green channel and metallic in the blue one, both bindings share one texture
+    if let Some(info) = pbr.metallic_roughness_texture(){
+        if let Some(binding) = data.load_texture(&info.texture(),info.tex_coord(),ColorSpace::Linear){
+            material.roughness_texture = TextureBinding{channel:Some(1),texture:binding.texture.clone(),sampler:binding.sampler};
+            material.metallic_texture = TextureBinding{channel:Some(2),..binding};
+        }
+    }
+
+    if let Some(normal) = gltf_material.normal_texture(){
+        if let Some(binding) = data.load_texture(&normal.texture(),normal.tex_coord(),ColorSpace::Linear){
+            material.normal_texture = binding;
+        }
+    }
+    if let Some(occlusion) = gltf_material.occlusion_texture(){
+        //occlusion is in the red channel, often of the metallic-roughness texture
+        if let Some(binding) = data.load_texture(&occlusion.texture(),occlusion.tex_coord(),ColorSpace::Linear){
+            material.occlusion_texture = TextureBinding{channel:Some(0),..binding};
+            material.occlusion_strength = occlusion.strength();
+        }
+    }
+
+    material.emissive = glam::Vec3::from(gltf_material.emissive_factor());
+    if let Some(info) = gltf_material.emissive_texture(){
+        if let Some(binding) = data.load_texture(&info.texture(),info.tex_coord(),ColorSpace::Srgb){
+            material.emissive_texture = binding;
+        }
+    }
+
+    match gltf_material.alpha_mode(){
+        gltf::material::AlphaMode::Opaque => {},
+        gltf::material::AlphaMode::Mask => material.alpha_cutoff = Some(gltf_material.alpha_cutoff().unwrap_or(0.5)),
+        gltf::material::AlphaMode::Blend => material.blend_mode = BlendMode::Alpha,
+    }
+    material
+}
+
+/// Adds the faces of one primitive to a model, with the node transform baked into the vertices.
+/// Primitives that cannot be drawn are returned as errors, missing normals and uvs are generated.
+fn load_primitive(model:&mut Model,primitive:&gltf::Primitive,transform:glam::Mat4,data:&mut GltfData) -> Result<(),LoadError>{
+    let name = format!("{}.{}",model.name,primitive.index());
+    if primitive.mode()!=gltf::mesh::Mode::Triangles {
+        return Err(LoadError::Parse{path:data.path.clone(),message:format!("Primitive {} uses {:?}, only triangle lists are supported",name,primitive.mode())});
+    }
+    let buffers = &data.buffers;
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let positions:Vec<[f32;3]> = reader.read_positions()
+        .ok_or(LoadError::MissingAttribute{model:name.clone(),attribute:"positions".to_string()})?.collect();
+    let normals:Option<Vec<[f32;3]>> = reader.read_normals().map(|n| n.collect());
+    let uvs:Option<Vec<[f32;2]>> = reader.read_tex_coords(0).map(|uvs| uvs.into_f32().collect());
+    let tangents:Option<Vec<[f32;4]>> = reader.read_tangents().map(|t| t.collect());
+    let mut indices:Vec<usize> = match reader.read_indices(){
+        Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+        None => (0..positions.len()).collect(),
+    };
+    let count = positions.len();
+    if normals.as_ref().is_some_and(|n| n.len()!=count) || uvs.as_ref().is_some_and(|uv| uv.len()!=count)
+        || tangents.as_ref().is_some_and(|t| t.len()!=count) || indices.iter().any(|i| *i>=count) {
+        return Err(LoadError::Parse{path:data.path.clone(),message:format!("Primitive {} has inconsistent attribute counts",name)});
+    }
+    if normals.is_none() {
+        data.warnings.push(LoadError::MissingAttribute{model:name.clone(),attribute:"normals".to_string()});
+    }
+    if uvs.is_none() {
+        data.warnings.push(LoadError::MissingAttribute{model:name.clone(),attribute:"texture coordinates".to_string()});
+    }
+
+    let normal_matrix = glam::Mat3::from_mat4(transform).inverse().transpose();
+    let mut vertices:Vec<Vertex> = (0..count).map(|i| {
+        let normal = normals.as_ref().map_or(glam::Vec3::ZERO,|n| (normal_matrix*glam::Vec3::from(n[i])).normalize());
+        let uv = uvs.as_ref().map_or([0.0,0.0],|uv| uv[i]);
+        Vertex{
+            position: transform.transform_point3(glam::Vec3::from(positions[i])),
+            //glTF puts v=0 at the first row of the image, the textures here put it at v=1
+            uv: glam::Vec2::new(uv[0],1.0-uv[1]),
+            normal,
+            tangent: glam::Vec4::ZERO,
+        }
+    }).collect();
+
+    //mirroring transforms flip the winding
+    if transform.determinant()<0.0 {
+        indices.chunks_exact_mut(3).for_each(|face| face.swap(1,2));
+    }
+    let gltf_material = primitive.material();
+    let material = match data.materials.get(&gltf_material.index()){
+        Some(material) => material.clone(),
+        None => {
+            let material = Arc::new(load_material(&gltf_material,data));
+            data.materials.insert(gltf_material.index(),material.clone());
+            material
+        },
+    };
+    let mut faces = build_faces(&vertices,&indices,model.material_index(&material));
+    if normals.is_none() {
+        generate_normals(&mut vertices,&mut faces,data.options.normals);
+    }
+
+    //provided tangents are only valid together with the normals they were computed from
+    match (tangents,&normals){
+        (Some(tangents),Some(_)) => {
+            //mirroring also mirrors the bitangent computed from the transformed normal and tangent
+            let sign = transform.determinant().signum();
+            for (vertex,tangent) in vertices.iter_mut().zip(tangents.iter()){
+                let direction = transform.transform_vector3(glam::Vec3::new(tangent[0],tangent[1],tangent[2]));
+                let direction = (direction-vertex.normal*vertex.normal.dot(direction)).normalize_or_zero();
+                vertex.tangent = glam::Vec4::from((direction,tangent[3]*sign));
+            }
+        },
+        _ => generate_tangents(&mut vertices,&faces),
+    }
+
+    let offset = model.vertices.len();
+    for face in faces.iter_mut(){
+        face.vertices = face.vertices.map(|v| v+offset);
+    }
+    model.vertices.extend(vertices);
+    model.faces.extend(faces);
+    Ok(())
+}
+
+fn load_node(node:&gltf::Node,parent:glam::Mat4,data:&mut GltfData,models:&mut Vec<Model>){
+    let transform = parent*glam::Mat4::from_cols_array_2d(&node.transform().matrix());
+    if let Some(mesh) = node.mesh(){
+        let mut model = Model{
+            name: mesh.name().map(String::from).unwrap_or(format!("mesh{}",mesh.index())),
+            vertices: Vec::new(),
+            faces: Vec::new(),
+            materials: Vec::new(),
+        };
+        for primitive in mesh.primitives(){
+            if let Err(e) = load_primitive(&mut model,&primitive,transform,data){
+                data.warnings.push(e);
+            }
+        }
+        if !model.faces.is_empty() {
+            models.push(model);
+        }
+    }
+    for child in node.children(){
+        load_node(&child,transform,data,models);
+    }
+}
+
+/// Loads the default scene of a .gltf or .glb file, one model per mesh instance with a material per primitive.
+/// Primitives that cannot be loaded are skipped and reported as warnings.
+pub fn load_gltf(path: &str,options:&LoadOptions,cache:&mut TextureCache) -> Result<LoadedModels,LoadError>{
+    println!("Loading glTF from {}", path);
+    let (document,buffers,images) = gltf::import(path).map_err(|e| match e{
+        gltf::Error::Io(e) => LoadError::Io{path:path.to_string(),message:e.to_string()},
+        e => LoadError::Parse{path:path.to_string(),message:e.to_string()},
+    })?;
+    let scene = document.default_scene().or(document.scenes().next())
+        .ok_or(LoadError::Parse{path:path.to_string(),message:"no scene".to_string()})?;
+    let mut data = GltfData{path:path.to_string(),buffers,images,options:*options,materials:HashMap::new(),cache,warnings:Vec::new()};
+    let mut models = Vec::<Model>::new();
+    for node in scene.nodes(){
+        load_node(&node,glam::Mat4::IDENTITY,&mut data,&mut models);
+    }
+    Ok(LoadedModels{models,warnings:data.warnings})
+}
@@ -1,5 +1,6 @@
 #![forbid(unsafe_code)]
 #![allow(dead_code)]
+#![allow(clippy::needless_return, clippy::redundant_field_names, clippy::too_many_arguments)]
 
 use winit::{
     event::{Event,WindowEvent, VirtualKeyCode},
@@ -16,8 +17,66 @@ pub mod draw;
 pub mod model;
 pub mod shader;
 pub mod camera;
+pub mod framebuffer;
+pub mod present;
+pub mod clip;
+pub mod render_state;
+pub mod raster;
+pub mod texture;
+pub mod color;
+pub mod tonemap;
+pub mod shadow;
+pub mod environment;
+pub mod gltf_loader;
+pub mod texture_cache;
 
 
+fn parse_vec3(value:&str) -> Result<glam::Vec3,String>{
+    let components = value.split(',')
+        .map(|c| c.trim().parse::<f32>())
+        .collect::<Result<Vec<f32>,_>>()
+        .map_err(|e| format!("Invalid vector {}: {}",value,e))?;
+    if components.len()!=3 {
+        return Err(format!("Invalid vector {}: expected 3 components",value));
+    }
+    Ok(glam::Vec3::new(components[0],components[1],components[2]))
+}
+
+fn parse_number<T:std::str::FromStr>(name:&str,value:&str) -> Result<T,String> where T::Err: std::fmt::Display{
+    value.trim().parse::<T>().map_err(|e| format!("Invalid {} {}: {}",name,value,e))
+}
+
+/// Prints the error of a failed setup step and exits, for errors the renderer cannot recover from
+fn or_exit<T>(result:Result<T,String>) -> T{
+    match result{
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("{}",e);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn update_scene(globals:&mut shader::GlobalData,time:f32,eye:Option<glam::Vec3>,target:glam::Vec3,light:Option<glam::Vec3>){
+    globals.time = time;
+
+    let eye = eye.unwrap_or_else(|| glam::Vec3::new(time.sin()*2.0,1.0,time.cos()*2.0));
+    let up = glam::Vec3::new(0.0,1.0,0.0);
+    globals.camera.look_at(eye,target,up);
+
+    //globals.camera.look_at(glam::Vec3::new(0.0,0.0,(globals.time*0.1).sin()*2.0), glam::Vec3::new(0.0,0.0,50.0), glam::Vec3::new(0.0,1.0,0.0));
+
+    if let shader::Light::Point{position,..} = &mut globals.lights[0] {
+        *position = light.unwrap_or(eye);
+    }
+}
+
+fn update_shadows(globals:&mut shader::GlobalData,models:&[model::Model],shadow_pass:&mut Option<shadow::ShadowPass>){
+    if let Some(shadow_pass) = shadow_pass {
+        globals.shadows = shadow_pass.render(models,&globals.lights,globals.time);
+    }
+}
+
 fn main() {
 
     let matches = Command::new("EmyRenderer")
@@ -29,7 +88,12 @@ fn main() {
                 .short('p')
                 .long("path")
                 .takes_value(true)
-                .help("The path of the model to render"))
+                .help("The path of the model to render, .obj, .gltf or .glb"))
+        .arg(Arg::new("Normals")
+                .long("normals")
+                .takes_value(true)
+                .help("How normals are generated for meshes without them: smooth, flat or a crease angle in degrees")
+                .default_value("smooth"))
         .arg(Arg::new("Use Wireframe")
                 .long("use_wireframe")
                 .help("Draws the model in wireframe"))
@@ -49,15 +113,208 @@ fn main() {
                 .short('d')
                 .long("debug")
                 .help("Enables debug mode"))
+        .arg(Arg::new("Output")
+                .short('o')
+                .long("output")
+                .takes_value(true)
+                .help("Renders a single frame to this image (png, exr, ...) and exits without opening a window"))
+        .arg(Arg::new("Eye")
+                .long("eye")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .help("The camera position as x,y,z, orbits the model over time if not set"))
+        .arg(Arg::new("Target")
+                .long("target")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .help("The point the camera looks at as x,y,z")
+                .default_value("0,0,0"))
+        .arg(Arg::new("Fov")
+                .long("fov")
+                .takes_value(true)
+                .help("The vertical field of view of the camera in degrees")
+                .default_value("60"))
+        .arg(Arg::new("Time")
+                .short('t')
+                .long("time")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .help("The time in seconds of the frame rendered with --output")
+                .default_value("0"))
+        .arg(Arg::new("Cull")
+                .long("cull")
+                .takes_value(true)
+                .possible_values(["none","back","front"])
+                .help("Which faces to skip when rasterizing")
+                .default_value("none"))
+        .arg(Arg::new("Front Face")
+                .long("front_face")
+                .takes_value(true)
+                .possible_values(["ccw","cw"])
+                .help("The winding of front facing triangles")
+                .default_value("ccw"))
+        .arg(Arg::new("Threads")
+                .long("threads")
+                .takes_value(true)
+                .help("How many threads to render with, 0 uses every core")
+                .default_value("0"))
+        .arg(Arg::new("MSAA")
+                .long("msaa")
+                .takes_value(true)
+                .possible_values(["1","2","4","8"])
+                .help("Samples per pixel used for anti-aliasing")
+                .default_value("1"))
+        .arg(Arg::new("Tone Map")
+                .long("tonemap")
+                .takes_value(true)
+                .possible_values(["none","reinhard","aces"])
+                .help("The curve used to fit HDR colors on the display")
+                .default_value("aces"))
+        .arg(Arg::new("Exposure")
+                .long("exposure")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .help("Exposure adjustment in stops applied before tone mapping")
+                .default_value("0"))
+        .arg(Arg::new("Gamma")
+                .long("gamma")
+                .takes_value(true)
+                .help("Encodes the output with this gamma instead of the sRGB curve"))
+        .arg(Arg::new("Shader")
+                .long("shader")
+                .takes_value(true)
+                .possible_values(["lit","pbr","unlit"])
+                .help("The shading model used for every material")
+                .default_value("lit"))
+        .arg(Arg::new("Environment")
+                .long("environment")
+                .takes_value(true)
+                .help("An equirectangular .hdr or .exr image used for lighting and as the background"))
+        .arg(Arg::new("Light")
+                .long("light")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .help("The light position as x,y,z, follows the camera if not set"))
+        .arg(Arg::new("Sun")
+                .long("sun")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .help("Adds a directional light shining along x,y,z"))
+        .arg(Arg::new("Shadows")
+                .long("shadows")
+                .help("Renders shadow maps for the lights"))
+        .arg(Arg::new("Shadow Size")
+                .long("shadow_size")
+                .takes_value(true)
+                .help("The resolution of each shadow map face")
+                .default_value("1024"))
+        .arg(Arg::new("Shadow Bias")
+                .long("shadow_bias")
+                .takes_value(true)
+                .help("Constant shadow depth bias in world units")
+                .default_value("0.01"))
+        .arg(Arg::new("PCF")
+                .long("pcf")
+                .takes_value(true)
+                .help("Radius in texels of the shadow filter, 0 for hard shadows")
+                .default_value("1"))
         .get_matches();
 
     let path = matches.value_of("Path").unwrap_or("");
     let is_wireframe = matches.is_present("Use Wireframe");
-    let width = matches.value_of("Width").unwrap_or("1280").parse::<u32>().unwrap();
-    let height = matches.value_of("Height").unwrap_or("720").parse::<u32>().unwrap();
+    let width = or_exit(parse_number("width",matches.value_of("Width").unwrap_or("1280")));
+    let height = or_exit(parse_number("height",matches.value_of("Height").unwrap_or("720")));
     let is_debug = matches.is_present("Debug");
+    let output = matches.value_of("Output");
+    let eye = matches.value_of("Eye").map(|v| or_exit(parse_vec3(v)));
+    let target = or_exit(parse_vec3(matches.value_of("Target").unwrap_or("0,0,0")));
+    let fov = or_exit(parse_number("field of view",matches.value_of("Fov").unwrap_or("60")));
+    let frame_time = or_exit(parse_number("time",matches.value_of("Time").unwrap_or("0")));
+    let cull_mode = or_exit(render_state::CullMode::parse(matches.value_of("Cull").unwrap_or("none")));
+    let front_face = or_exit(render_state::FrontFace::parse(matches.value_of("Front Face").unwrap_or("ccw")));
+    let threads = or_exit(parse_number("thread count",matches.value_of("Threads").unwrap_or("0")));
+    let msaa = or_exit(parse_number("sample count",matches.value_of("MSAA").unwrap_or("1")));
+    let tone_map = or_exit(tonemap::ToneMapOperator::parse(matches.value_of("Tone Map").unwrap_or("aces")));
+    let exposure = or_exit(parse_number("exposure",matches.value_of("Exposure").unwrap_or("0")));
+    let gamma = matches.value_of("Gamma").map(|v| or_exit(parse_number("gamma",v)));
+    let light = matches.value_of("Light").map(|v| or_exit(parse_vec3(v)));
+    let sun = matches.value_of("Sun").map(|v| or_exit(parse_vec3(v)));
+    let shadows = matches.is_present("Shadows");
+    let shadow_settings = shadow::ShadowSettings{
+        resolution: or_exit(parse_number("shadow size",matches.value_of("Shadow Size").unwrap_or("1024"))),
+        bias: or_exit(parse_number("shadow bias",matches.value_of("Shadow Bias").unwrap_or("0.01"))),
+        pcf_radius: or_exit(parse_number("filter radius",matches.value_of("PCF").unwrap_or("1"))),
+        ..shadow::ShadowSettings::default()
+    };
     //Load models
-    let models = model::load_obj(path).expect("Failed to load model");
+    let load_options = model::LoadOptions{
+        normals: or_exit(model::NormalMode::parse(matches.value_of("Normals").unwrap_or("smooth"))),
+    };
+    let mut texture_cache = texture_cache::TextureCache::new();
+    let loaded = match model::load(path,&load_options,&mut texture_cache){
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("Failed to load model: {}",e);
+            std::process::exit(1);
+        },
+    };
+    for warning in loaded.warnings.iter(){
+        println!("Warning: {}",warning);
+    }
+    let models = loaded.models;
+    println!("Loaded {} textures using {:.1} MiB",texture_cache.len(),texture_cache.memory_size() as f32/(1024.0*1024.0));
+
+    let light1 = shader::Light::Point{
+        position: glam::Vec3::new(-1.0, -1.0, 2.0),
+        color: glam::Vec3::new(1.0, 1.0, 1.0),
+        intensity: 10.0,
+        range: None,
+    };
+    let mut lights = vec![light1];
+    if let Some(direction) = sun {
+        lights.push(shader::Light::Directional{
+            direction,
+            color: glam::Vec3::new(1.0, 0.95, 0.9),
+            intensity: 2.0,
+        });
+    }
+    
+    let mut globals = shader::GlobalData{
+        ambient_light: glam::Vec3::new(0.1, 0.1, 0.1),
+        lights,
+        time:0.0,
+        camera: camera::Camera::with_fov(width,height,fov),
+        shadows: Vec::new(),
+        environment: matches.value_of("Environment").map(|path| or_exit(environment::Environment::load(path))),
+    };
+
+    let shader:Box<dyn shader::Shader> = match matches.value_of("Shader").unwrap_or("lit"){
+        "pbr" => Box::new(shader::PbrShader{}),
+        "unlit" => Box::new(shader::UnlitShader{}),
+        _ => Box::new(shader::LitShader{}),
+    };
+    //let shader = shader::DebugShader{mode:shader::DebugMode::Bitangent};
+
+    let mut canvas = draw::Canvas::new(width, height);
+    canvas.render_state.cull_mode = cull_mode;
+    canvas.render_state.front_face = front_face;
+    or_exit(canvas.set_thread_count(threads));
+    or_exit(canvas.set_sample_count(msaa));
+    canvas.tone_mapping = tonemap::ToneMapping{operator:tone_map,exposure,gamma};
+    let mut shadow_pass = if shadows {Some(or_exit(shadow::ShadowPass::new(shadow_settings,threads)))} else {None};
+
+    if let Some(output) = output {
+        update_scene(&mut globals,frame_time,eye,target,light);
+        update_shadows(&mut globals,&models,&mut shadow_pass);
+        canvas.clear_frame();
+        canvas.draw_scene(&models,shader.as_ref(),&globals,is_wireframe,is_debug);
+        let mut sink = present::ImageSink::new(output);
+        if let Err(e) = canvas.present(&mut sink){
+            eprintln!("{}",e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
     //Create window
     let event_loop = EventLoop::new();
@@ -72,24 +329,7 @@ fn main() {
             .build(&event_loop)
             .unwrap()
     };
-    let mut canvas = draw::Canvas::new(width, height, &window).expect("There was an error creating the frame buffer");
-
-    let light1 = shader::Light{
-        position: glam::Vec3::new(-1.0, -1.0, 2.0),
-        color: glam::Vec3::new(1.0, 1.0, 1.0),
-        intensity: 1.0,
-    };
-    
-    let mut globals = shader::GlobalData{
-        ambient_light: glam::Vec3::new(0.1, 0.1, 0.1),
-        lights: vec![light1],
-        time:0.0,
-        camera: camera::Camera::new(width,height),
-    };
-
-    let shader = shader::LitShader{};
-    //let shader = shader::DebugShader{mode:shader::DebugMode::Bitangent};
-
+    let mut sink = or_exit(present::PixelsSink::new(width, height, &window));
     
     let time = Instant::now();
 
@@ -105,36 +345,21 @@ fn main() {
             },
             Event::MainEventsCleared => {
                 let t = time.elapsed().as_secs_f32();
-                globals.time = t;
-
-                let eye = glam::Vec3::new(globals.time.sin()*2.0,1.0,globals.time.cos()*2.0);              
-                let center = glam::Vec3::new(0.0,0.0,0.0);
-                let up = glam::Vec3::new(0.0,1.0,0.0);
-                globals.camera.look_at(eye,center,up);
-
-                //globals.camera.look_at(glam::Vec3::new(0.0,0.0,(globals.time*0.1).sin()*2.0), glam::Vec3::new(0.0,0.0,50.0), glam::Vec3::new(0.0,1.0,0.0));
-                
-                globals.lights[0].position = eye;
-                
+                update_scene(&mut globals,t,eye,target,light);
 
                 let start = Instant::now();
+                update_shadows(&mut globals,&models,&mut shadow_pass);
                 canvas.clear_frame();
-                for model in models.iter(){
-                    canvas.draw_model(&model,&shader,&globals,is_wireframe,is_debug);
-                }
+                canvas.draw_scene(&models,shader.as_ref(),&globals,is_wireframe,is_debug);
                 let elapsed = start.elapsed();
                 window.set_title(&format!("EmyRenderer | Frame Time: {} | FPS: {}", elapsed.as_millis(), 1.0 / elapsed.as_secs_f32()));
-                canvas.render();
+                canvas.present(&mut sink).unwrap();
             },
             _ => ()
         }
 
-        if input.update(&event){
-            if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
-                *control_flow = ControlFlow::Exit;
-                return;
-            }
-            
+        if input.update(&event) && (input.key_pressed(VirtualKeyCode::Escape) || input.quit()) {
+            *control_flow = ControlFlow::Exit;
         }
 
 
@@ -1,22 +1,118 @@
-use crate::shader::Texture;
+use crate::texture::{Texture,TextureBinding,ColorSpace,Sampler,FilterMode,WrapMode};
+use crate::texture_cache::TextureCache;
+use crate::shader::VertOutput;
+use crate::render_state::BlendMode;
 use std::path;
+use std::fmt;
+use std::collections::{HashMap,HashSet};
+use std::sync::Arc;
 
 pub struct Material{
-    pub albedo_texture: Texture,
-    pub normal_texture: Texture,
-    pub specular_texture: Texture,
+    /// Base color multiplied by the albedo texture, Kd in MTL files
+    pub albedo: glam::Vec4,
+    pub albedo_texture: TextureBinding,
+    pub normal_texture: TextureBinding,
+    /// Color of the highlights of the lit shader, multiplied by the specular texture
+    pub specular: glam::Vec3,
+    pub specular_texture: TextureBinding,
+    /// Phong exponent of the highlights, multiplied by the red channel of the shininess texture
+    pub shininess: f32,
+    pub shininess_texture: TextureBinding,
+    /// Factor of the diffuse color reflecting the ambient light in the lit shader, multiplied by the ambient texture
+    pub ambient: glam::Vec3,
+    pub ambient_texture: TextureBinding,
+    /// Opacity mask from map_d, stored in the alpha channel
+    pub alpha_texture: TextureBinding,
+    pub dissolve: f32,
+    /// Fragments with a lower alpha are discarded
+    pub alpha_cutoff: Option<f32>,
+    pub blend_mode: BlendMode,
+    /// Metallic-roughness inputs of the PBR shader, each factor is multiplied by the red channel of its texture
+    pub metallic: f32,
+    pub roughness: f32,
+    pub metallic_texture: TextureBinding,
+    pub roughness_texture: TextureBinding,
+    pub occlusion_texture: TextureBinding,
+    /// How much of the occlusion texture is applied, from none at 0 to all of it at 1
+    pub occlusion_strength: f32,
+    /// Emitted light, multiplied by the emissive texture
+    pub emissive: glam::Vec3,
+    pub emissive_texture: TextureBinding,
 }
 
+impl Default for Material{
+    fn default() -> Material{
+        Material{
+            albedo: glam::Vec4::ONE,
+            albedo_texture: TextureBinding::new(Texture::white()),
+            normal_texture: TextureBinding::new(Texture::normal_default()),
+            specular: glam::Vec3::ONE,
+            specular_texture: TextureBinding::new(Texture::white()),
+            shininess: 256.0,
+            shininess_texture: TextureBinding::new(Texture::white()),
+            ambient: glam::Vec3::ONE,
+            ambient_texture: TextureBinding::new(Texture::white()),
+            alpha_texture: TextureBinding::new(Texture::white()),
+            dissolve: 1.0,
+            alpha_cutoff: None,
+            blend_mode: BlendMode::Opaque,
+            metallic: 0.0,
+            roughness: 0.5,
+            metallic_texture: TextureBinding::new(Texture::white()),
+            roughness_texture: TextureBinding::new(Texture::white()),
+            occlusion_texture: TextureBinding::new(Texture::white()),
+            occlusion_strength: 1.0,
+            emissive: glam::Vec3::ZERO,
+            emissive_texture: TextureBinding::new(Texture::white()),
+        }
+    }
+}
+
+impl Material{
+    /// Samples one of the material textures at the uv of a fragment
+    pub fn sample(&self,texture:&TextureBinding,i:&VertOutput) -> glam::Vec4{
+        texture.sample(i.uv,i.uv_dx,i.uv_dy)
+    }
+
+    /// Linear base color of a fragment, alpha included
+    pub fn sample_albedo(&self,i:&VertOutput) -> glam::Vec4{
+        self.albedo * self.sample(&self.albedo_texture,i)
+    }
+
+    pub fn alpha(&self,i:&VertOutput,albedo_alpha:f32) -> f32{
+        albedo_alpha * self.dissolve * self.sample(&self.alpha_texture,i).w
+    }
+
+    pub fn is_cut_out(&self,alpha:f32) -> bool{
+        match self.alpha_cutoff{
+            Some(cutoff) => alpha<cutoff,
+            None => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
 pub struct Vertex{
     pub position: glam::Vec3,
     pub uv: glam::Vec2,
     pub normal: glam::Vec3,
-    pub tangent: glam::Vec3,
-    pub bitangent: glam::Vec3,
+    /// Unit tangent along increasing u, orthogonal to the normal. w is the handedness of the tangent frame,
+    /// the bitangent is cross(normal,tangent)*w like in glTF and MikkTSpace.
+    pub tangent: glam::Vec4,
 }
+
+impl Vertex{
+    pub fn bitangent(&self) -> glam::Vec3{
+        self.normal.cross(self.tangent.truncate())*self.tangent.w
+    }
+}
+
 pub struct Face{
     pub vertices: [usize; 3],
+    /// Index in the materials of the model
+    pub material: usize,
     pub normal: glam::Vec3,
+    /// Derivatives of the position along u and v, zero when the uvs do not span the face
     pub tangent: glam::Vec3,
     pub bitangent: glam::Vec3,
 }
@@ -25,120 +121,845 @@ pub struct Model {
     pub name: String,
     pub vertices: Vec<Vertex>,
     pub faces: Vec<Face>,
-    pub material:Material
+    /// Materials used by the faces, shared with the other models of the file that use them
+    pub materials: Vec<Arc<Material>>,
+}
+
+impl Model{
+    pub fn material(&self,face:&Face) -> &Material{
+        &self.materials[face.material]
+    }
+
+    /// Index of a material in the model, adding it if the model does not use it yet
+    pub fn material_index(&mut self,material:&Arc<Material>) -> usize{
+        match self.materials.iter().position(|m| Arc::ptr_eq(m,material)){
+            Some(index) => index,
+            None => {
+                self.materials.push(material.clone());
+                self.materials.len()-1
+            },
+        }
+    }
+}
+
+/// A problem found while loading a model. Fatal ones are returned as errors, the others
+/// are worked around and returned as warnings next to the models.
+#[derive(Debug)]
+pub enum LoadError{
+    /// A file could not be read
+    Io{path:String,message:String},
+    /// A file was read but its content is invalid
+    Parse{path:String,message:String},
+    /// A texture of a material could not be loaded, the material uses its default texture instead
+    MissingTexture{material:String,path:String,message:String},
+    /// A mesh has no data for a vertex attribute, which gets generated or defaulted
+    MissingAttribute{model:String,attribute:String},
+}
+
+impl fmt::Display for LoadError{
+    fn fmt(&self,f:&mut fmt::Formatter) -> fmt::Result{
+        match self{
+            LoadError::Io{path,message} => write!(f,"Failed to read {}: {}",path,message),
+            LoadError::Parse{path,message} => write!(f,"Invalid {}: {}",path,message),
+            LoadError::MissingTexture{material,path,message} => write!(f,"Material {} is missing texture {}: {}",material,path,message),
+            LoadError::MissingAttribute{model,attribute} => write!(f,"Model {} has no {}",model,attribute),
+        }
+    }
+}
+
+impl std::error::Error for LoadError{}
+
+/// How normals are generated for meshes that do not have them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NormalMode{
+    /// Every face uses its own normal
+    Flat,
+    /// Normals are averaged across all the faces sharing a vertex
+    Smooth,
+    /// Smooth, except across edges where the faces meet at more than this angle in radians
+    Crease(f32),
+}
+
+impl NormalMode{
+    /// Parses "flat", "smooth" or a crease angle in degrees
+    pub fn parse(value:&str) -> Result<NormalMode,String>{
+        match value.to_lowercase().as_str(){
+            "flat" => Ok(NormalMode::Flat),
+            "smooth" => Ok(NormalMode::Smooth),
+            angle => angle.parse::<f32>().map(|angle| NormalMode::Crease(angle.to_radians()))
+                .map_err(|_| format!("Unknown normal mode {}, expected flat, smooth or an angle in degrees",value)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LoadOptions{
+    pub normals: NormalMode,
+}
+
+impl Default for LoadOptions{
+    fn default() -> LoadOptions{
+        LoadOptions{
+            normals: NormalMode::Smooth,
+        }
+    }
+}
+
+pub struct LoadedModels{
+    pub models: Vec<Model>,
+    pub warnings: Vec<LoadError>,
+}
+
+/// Splits a MTL texture statement such as `-clamp on -s 2 2 decal.png` into the file name
+/// and the value of the -clamp option. The other standard options are skipped.
+fn parse_texture_statement(statement:&str) -> Result<(String,Option<bool>),String>{
+    let tokens:Vec<&str> = statement.split_whitespace().collect();
+    let mut clamp = None;
+    let mut i = 0;
+    while i<tokens.len() && tokens[i].starts_with('-'){
+        let option = tokens[i];
+        i += 1;
+        match option{
+            "-clamp" => {
+                clamp = match tokens.get(i){
+                    Some(&"on") => Some(true),
+                    Some(&"off") => Some(false),
+                    value => return Err(format!("Invalid -clamp value {:?} in {}",value,statement)),
+                };
+                i += 1;
+            },
+            "-blendu" | "-blendv" | "-cc" | "-bm" | "-boost" | "-imfchan" | "-texres" | "-type" => i += 1,
+            "-mm" => i += 2,
+            //offset, scale and turbulence take one to three numbers
+            "-o" | "-s" | "-t" => {
+                let end = (i+3).min(tokens.len());
+                while i<end && tokens[i].parse::<f32>().is_ok(){
+                    i += 1;
+                }
+            },
+            _ => return Err(format!("Unknown texture option {} in {}",option,statement)),
+        }
+    }
+    if i>=tokens.len(){
+        return Err(format!("Missing texture file in {}",statement));
+    }
+    Ok((tokens[i..].join(" "),clamp))
+}
+
+/// Loads the texture of a MTL texture statement through the cache, -clamp on overrides the wrap mode of the material.
+/// A file that cannot be loaded is pushed to `warnings` and gives None.
+fn load_texture(directory:&path::Path,statement:&str,color_space:ColorSpace,sampler:&Sampler,material:&str,cache:&mut TextureCache,warnings:&mut Vec<LoadError>) -> Result<Option<TextureBinding>,String>{
+    let (file,clamp) = parse_texture_statement(statement)?;
+    let file = directory.join(file);
+    let texture = match cache.load(&file,color_space){
+        Ok(texture) => texture,
+        Err(message) => {
+            warnings.push(LoadError::MissingTexture{material:material.to_string(),path:file.to_string_lossy().into_owned(),message});
+            return Ok(None);
+        },
+    };
+    let mut sampler = *sampler;
+    match clamp{
+        Some(true) => {sampler.wrap_u = WrapMode::ClampToEdge; sampler.wrap_v = WrapMode::ClampToEdge;},
+        Some(false) => {sampler.wrap_u = WrapMode::Repeat; sampler.wrap_v = WrapMode::Repeat;},
+        None => {},
+    }
+    Ok(Some(TextureBinding{texture,sampler,channel:None}))
+}
+
+/// Reads a number from a statement that tobj does not know about
+fn parse_float(obj_material:&tobj::Material,key:&str) -> Result<Option<f32>,String>{
+    match obj_material.unknown_param.get(key){
+        Some(value) => value.trim().parse::<f32>().map(Some).map_err(|e| format!("Invalid {} {}: {}",key,value,e)),
+        None => Ok(None),
+    }
+}
+
+/// Parses an "r g b" color, a single value is used for all three channels
+fn parse_color(value:&str) -> Result<glam::Vec3,String>{
+    let values = value.split_whitespace().map(|v| v.parse::<f32>()).collect::<Result<Vec<f32>,_>>()
+        .map_err(|e| format!("Invalid color {}: {}",value,e))?;
+    match values[..]{
+        [v] => Ok(glam::Vec3::splat(v)),
+        [r,g,b] => Ok(glam::Vec3::new(r,g,b)),
+        _ => Err(format!("Invalid color {}, expected 1 or 3 values",value)),
+    }
+}
+
+/// Reads the non standard sampler statements of a material: filter, anisotropy, wrap, wrap_u, wrap_v and border_color
+fn parse_sampler(obj_material:&tobj::Material) -> Result<Sampler,String>{
+    let params = &obj_material.unknown_param;
+    let mut sampler = Sampler::default();
+    if let Some(filter) = params.get("filter"){
+        sampler.filter = FilterMode::parse(filter.trim())?;
+    }
+    if let Some(anisotropy) = params.get("anisotropy"){
+        let anisotropy = anisotropy.trim().parse::<u32>().map_err(|e| format!("Invalid anisotropy {}: {}",anisotropy,e))?;
+        sampler.max_anisotropy = anisotropy.max(1);
+    }
+    if let Some(wrap) = params.get("wrap"){
+        sampler.wrap_u = WrapMode::parse(wrap.trim())?;
+        sampler.wrap_v = sampler.wrap_u;
+    }
+    if let Some(wrap) = params.get("wrap_u"){
+        sampler.wrap_u = WrapMode::parse(wrap.trim())?;
+    }
+    if let Some(wrap) = params.get("wrap_v"){
+        sampler.wrap_v = WrapMode::parse(wrap.trim())?;
+    }
+    if let Some(color) = params.get("border_color"){
+        let values = color.split_whitespace().map(|v| v.parse::<f32>()).collect::<Result<Vec<f32>,_>>()
+            .map_err(|e| format!("Invalid border_color {}: {}",color,e))?;
+        sampler.border_color = match values[..]{
+            [r,g,b] => glam::Vec4::new(r,g,b,1.0),
+            [r,g,b,a] => glam::Vec4::new(r,g,b,a),
+            _ => return Err(format!("Invalid border_color {}, expected 3 or 4 values",color)),
+        };
+    }
+    Ok(sampler)
+}
+
+/// Builds the faces of a counter-clockwise triangle list, with face normals and tangents
+pub fn build_faces(vertices:&[Vertex],indices:&[usize],material:usize) -> Vec<Face>{
+    let mut faces = Vec::<Face>::new();
+    for i in 0..indices.len()/3{
+        let face_vertices = [
+            indices[i*3],
+            indices[i*3+1],
+            indices[i*3+2],
+        ];
+        let face_normal = (vertices[face_vertices[1]].position-vertices[face_vertices[0]].position)
+                                .cross(vertices[face_vertices[2]].position-vertices[face_vertices[0]].position)
+                                .normalize_or_zero();
+
+        let deltapos1 = vertices[face_vertices[1]].position-vertices[face_vertices[0]].position;
+        let deltapos2 = vertices[face_vertices[2]].position-vertices[face_vertices[1]].position;
+        let deltauv1 = vertices[face_vertices[1]].uv-vertices[face_vertices[0]].uv;
+        let deltauv2 = vertices[face_vertices[2]].uv-vertices[face_vertices[1]].uv;
+
+        //faces with a zero area in uv space have no defined tangent
+        let r = 1.0/(deltauv1.x*deltauv2.y-deltauv1.y*deltauv2.x);
+        let (tangent,bitangent) = if r.is_finite() {
+            ((deltapos1*deltauv2.y-deltapos2*deltauv1.y)*r,(deltapos2*deltauv1.x-deltapos1*deltauv2.x)*r)
+        } else {
+            (glam::Vec3::ZERO,glam::Vec3::ZERO)
+        };
+
+        faces.push(Face{
+            vertices:face_vertices,
+            material:material,
+            normal:face_normal,
+            tangent:tangent,
+            bitangent:bitangent,
+        });
+    }
+    faces
+}
+
+/// Angle of a face at each of its corners
+fn corner_angles(vertices:&[Vertex],face:&Face) -> [f32; 3]{
+    let mut angles = [0.0; 3];
+    for (corner,angle) in angles.iter_mut().enumerate(){
+        let p = vertices[face.vertices[corner]].position;
+        let a = (vertices[face.vertices[(corner+1)%3]].position-p).normalize_or_zero();
+        let b = (vertices[face.vertices[(corner+2)%3]].position-p).normalize_or_zero();
+        *angle = a.dot(b).clamp(-1.0,1.0).acos();
+    }
+    angles
+}
+
+/// Per vertex tangents, following the MikkTSpace approach: face tangents and bitangents are summed with
+/// the angle of the face at the vertex as weight, the tangent is made orthogonal to the vertex normal with
+/// Gram-Schmidt and the handedness is taken from the summed bitangent. Faces without a uv area are ignored,
+/// vertices left without a tangent get an arbitrary one orthogonal to their normal.
+pub fn generate_tangents(vertices:&mut [Vertex],faces:&[Face]){
+    let mut tangents = vec![glam::Vec3::ZERO; vertices.len()];
+    let mut bitangents = vec![glam::Vec3::ZERO; vertices.len()];
+    for face in faces.iter(){
+        //the face vectors have the uv scale baked in, only their directions are summed
+        let tangent = face.tangent.normalize_or_zero();
+        let bitangent = face.bitangent.normalize_or_zero();
+        for (v,angle) in face.vertices.iter().zip(corner_angles(vertices,face)){
+            tangents[*v] += tangent*angle;
+            bitangents[*v] += bitangent*angle;
+        }
+    }
+    for ((vertex,tangent),bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents){
+        let normal = vertex.normal;
+        let orthogonal = (tangent-normal*normal.dot(tangent)).normalize_or_zero();
+        let orthogonal = if orthogonal==glam::Vec3::ZERO {normal.any_orthonormal_vector()} else {orthogonal};
+        let handedness = if normal.cross(orthogonal).dot(bitangent)<0.0 {-1.0} else {1.0};
+        vertex.tangent = glam::Vec4::from((orthogonal,handedness));
+    }
+}
+
+/// Generates normals for meshes that have none. Corners get the average of the normals of the faces
+/// sharing their position, weighted by the angle of each face at that corner. Faces are only averaged
+/// with the ones allowed by the mode, and vertices whose corners end up with different normals are split.
+pub fn generate_normals(vertices:&mut Vec<Vertex>,faces:&mut [Face],mode:NormalMode){
+    let min_cos = match mode{
+        NormalMode::Flat => f32::INFINITY,
+        NormalMode::Smooth => f32::NEG_INFINITY,
+        NormalMode::Crease(angle) => angle.cos(),
+    };
+    let key = |position:glam::Vec3| position.to_array().map(f32::to_bits);
+    //corners are gathered by position so uv seams do not break the smoothing
+    let mut corners = HashMap::<[u32;3],Vec<(usize,usize)>>::new();
+    for (f,face) in faces.iter().enumerate(){
+        for (c,v) in face.vertices.iter().enumerate(){
+            corners.entry(key(vertices[*v].position)).or_default().push((f,c));
+        }
+    }
+    let angles:Vec<[f32;3]> = faces.iter().map(|face| corner_angles(vertices,face)).collect();
+
+    let mut assigned = vec![None; vertices.len()];
+    let mut splits = HashMap::<(usize,[u32;3]),usize>::new();
+    for f in 0..faces.len(){
+        for c in 0..3{
+            let v = faces[f].vertices[c];
+            let face_normal = faces[f].normal;
+            let mut sum = glam::Vec3::ZERO;
+            for (g,d) in corners[&key(vertices[v].position)].iter(){
+                let normal = faces[*g].normal;
+                //faces without an area have no normal of their own and take the one of their neighbours
+                if *g==f || face_normal==glam::Vec3::ZERO || face_normal.dot(normal)>=min_cos {
+                    sum += normal*angles[*g][*d];
+                }
+            }
+            let normal = if sum==glam::Vec3::ZERO {face_normal} else {sum.normalize()};
+            match assigned[v]{
+                None => {
+                    assigned[v] = Some(normal);
+                    vertices[v].normal = normal;
+                },
+                Some(existing) if existing==normal => {},
+                Some(_) => {
+                    let index = *splits.entry((v,key(normal))).or_insert_with(|| {
+                        vertices.push(Vertex{normal,..vertices[v]});
+                        vertices.len()-1
+                    });
+                    faces[f].vertices[c] = index;
+                },
+            }
+        }
+    }
+}
+
+/// Loads a model file, picking the importer from the extension
+pub fn load(path: &str,options:&LoadOptions,cache:&mut TextureCache) -> Result<LoadedModels,LoadError>{
+    let extension = path::Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    match extension.as_str(){
+        "gltf" | "glb" => crate::gltf_loader::load_gltf(path,options,cache),
+        _ => load_obj(path,options,cache),
+    }
+}
+
+/// Keywords of the statements of every material in the libraries of an OBJ file, by material name.
+/// tobj gives no way to tell a missing color from a black one, so the libraries are scanned again.
+/// Libraries that cannot be read are left out, tobj already reports them.
+fn material_statements(path:&str) -> HashMap<String,HashSet<String>>{
+    let directory = path::Path::new(path).parent().unwrap_or(path::Path::new(""));
+    let mut statements = HashMap::<String,HashSet<String>>::new();
+    let obj = std::fs::read_to_string(path).unwrap_or_default();
+    //like tobj, only the first file of a mtllib statement is read
+    let libraries = obj.lines().filter_map(|line| {
+        let mut words = line.split_whitespace();
+        if words.next()==Some("mtllib") {words.next()} else {None}
+    });
+    for library in libraries{
+        let mtl = std::fs::read_to_string(directory.join(library)).unwrap_or_default();
+        let mut current = None;
+        for line in mtl.lines().map(str::trim){
+            match line.split_whitespace().next(){
+                Some("newmtl") => current = Some(statements.entry(line[6..].trim().to_string()).or_default()),
+                Some(keyword) => if let Some(current) = current.as_mut() {current.insert(keyword.to_string());},
+                None => {},
+            }
+        }
+    }
+    statements
 }
 
-pub fn load_obj(path: &str) -> Result<Vec<Model>,String>{
+/// Builds the material of an OBJ mesh. Invalid statements fail the whole material,
+/// textures that cannot be loaded are reported in `warnings` and left to their defaults.
+/// `statements` are the keywords found in the block of the material, see material_statements.
+fn load_material(directory:&path::Path,obj_material:&tobj::Material,statements:Option<&HashSet<String>>,cache:&mut TextureCache,warnings:&mut Vec<LoadError>) -> Result<Material,String>{
+    let mut material = Material::default();
+    //the sampler only applies to loaded textures, the 1x1 defaults must not read the border color
+    let sampler = parse_sampler(obj_material)?;
+    let mut texture = |statement:&str,color_space:ColorSpace| load_texture(directory,statement,color_space,&sampler,&obj_material.name,cache,warnings);
+
+    let albedo_texture = &obj_material.diffuse_texture;
+    if !albedo_texture.is_empty() {
+        if let Some(binding) = texture(albedo_texture,ColorSpace::Srgb)? {
+            material.albedo_texture = binding;
+        }
+    }
+    let normal_texture = &obj_material.normal_texture;
+    if !normal_texture.is_empty() {
+        if let Some(binding) = texture(normal_texture,ColorSpace::Linear)? {
+            material.normal_texture = binding;
+        }
+    }
+    let specular_texture = &obj_material.specular_texture;
+    if !specular_texture.is_empty() {
+        if let Some(binding) = texture(specular_texture,ColorSpace::Linear)? {
+            material.specular_texture = binding;
+        }
+    }
+    let alpha_texture = &obj_material.dissolve_texture;
+    if !alpha_texture.is_empty() {
+        if let Some(mut binding) = texture(alpha_texture,ColorSpace::Linear)? {
+            //grayscale and RGBA opacity maps are both supported, opaque ones are read from red
+            binding.channel = Some(if binding.texture.is_translucent() {3} else {0});
+            material.alpha_texture = binding;
+        }
+    }
+    let ambient_texture = &obj_material.ambient_texture;
+    if !ambient_texture.is_empty() {
+        if let Some(binding) = texture(ambient_texture,ColorSpace::Srgb)? {
+            material.ambient_texture = binding;
+        }
+    }
+    let shininess_texture = &obj_material.shininess_texture;
+    if !shininess_texture.is_empty() {
+        if let Some(binding) = texture(shininess_texture,ColorSpace::Linear)? {
+            material.shininess_texture = binding;
+        }
+    }
+    //tobj reads missing colors as black, Kd and Ka keep their defaults unless the statement is in the library
+    let has_statement = |keyword:&str| statements.map(|statements| statements.contains(keyword)).unwrap_or(false);
+    let diffuse = glam::Vec3::from(obj_material.diffuse);
+    if has_statement("Kd") || diffuse!=glam::Vec3::ZERO {
+        material.albedo = glam::Vec4::from((diffuse,1.0));
+    }
+    let ambient = glam::Vec3::from(obj_material.ambient);
+    if has_statement("Ka") || ambient!=glam::Vec3::ZERO {
+        material.ambient = ambient;
+    }
+    material.specular = glam::Vec3::from(obj_material.specular);
+    material.shininess = obj_material.shininess.max(1.0);
+    material.dissolve = obj_material.dissolve;
+    //"alpha_cutoff" is not part of the MTL spec, alpha maps are treated as cutouts unless it is set to 0
+    material.alpha_cutoff = match obj_material.unknown_param.get("alpha_cutoff"){
+        Some(cutoff) => {
+            let cutoff = cutoff.trim().parse::<f32>().map_err(|e| format!("Invalid alpha_cutoff {}: {}",cutoff,e))?;
+            if cutoff>0.0 {Some(cutoff)} else {None}
+        },
+        None if !alpha_texture.is_empty() => Some(0.5),
+        None => None,
+    };
+    //"blend" is not part of the MTL spec, it allows picking a mode other than alpha blending
+    material.blend_mode = match obj_material.unknown_param.get("blend"){
+        Some(mode) => BlendMode::parse(mode)?,
+        None if material.alpha_cutoff.is_some() => BlendMode::Opaque,
+        None if material.dissolve<1.0 || material.albedo_texture.texture.is_translucent() => BlendMode::Alpha,
+        None => BlendMode::Opaque,
+    };
+
+    //PBR extension of the MTL format, which tobj leaves in the unknown parameters
+    let params = &obj_material.unknown_param;
+    if let Some(metallic) = parse_float(obj_material,"Pm")? {
+        material.metallic = metallic;
+    }
+    if let Some(roughness) = parse_float(obj_material,"Pr")? {
+        material.roughness = roughness;
+    }
+    if let Some(statement) = params.get("map_Pm") {
+        if let Some(binding) = texture(statement,ColorSpace::Linear)? {
+            material.metallic_texture = binding;
+            material.metallic = parse_float(obj_material,"Pm")?.unwrap_or(1.0);
+        }
+    }
+    if let Some(statement) = params.get("map_Pr") {
+        if let Some(binding) = texture(statement,ColorSpace::Linear)? {
+            material.roughness_texture = binding;
+            material.roughness = parse_float(obj_material,"Pr")?.unwrap_or(1.0);
+        }
+    }
+    //"map_ao" is not part of the extension, but a common name for occlusion maps
+    if let Some(statement) = params.get("map_ao") {
+        if let Some(binding) = texture(statement,ColorSpace::Linear)? {
+            material.occlusion_texture = binding;
+        }
+    }
+    if let Some(emissive) = params.get("Ke") {
+        material.emissive = parse_color(emissive)?;
+    }
+    if let Some(statement) = params.get("map_Ke") {
+        if let Some(binding) = texture(statement,ColorSpace::Srgb)? {
+            material.emissive_texture = binding;
+            if !params.contains_key("Ke") {
+                material.emissive = glam::Vec3::ONE;
+            }
+        }
+    }
+    Ok(material)
+}
+
+fn tobj_error(path:&str,error:tobj::LoadError) -> LoadError{
+    match error{
+        tobj::LoadError::OpenFileFailed | tobj::LoadError::ReadError => LoadError::Io{path:path.to_string(),message:error.to_string()},
+        _ => LoadError::Parse{path:path.to_string(),message:error.to_string()},
+    }
+}
+
+/// Vertices of a tobj mesh, with zero normals and uvs when the mesh has none
+fn obj_vertices(mesh:&tobj::Mesh,has_normals:bool,has_uvs:bool) -> Vec<Vertex>{
+    let mut vertices = Vec::<Vertex>::new();
+    for i in 0..mesh.positions.len()/3{
+        let position:glam::Vec3 = glam::Vec3::new(
+            mesh.positions[i*3],
+            mesh.positions[i*3+1],
+            mesh.positions[i*3+2],
+        );
+        //missing normals are generated from the faces
+        let normal = if has_normals {glam::Vec3::new(
+            mesh.normals[i*3],
+            mesh.normals[i*3+1],
+            mesh.normals[i*3+2],
+        )} else {glam::Vec3::ZERO};
+        let uv = if has_uvs {glam::Vec2::new(
+            mesh.texcoords[i*2],
+            mesh.texcoords[i*2+1],
+        )} else {glam::Vec2::ZERO};
+        let tangent = glam::Vec4::ZERO; //tangents are calculated from the faces
+
+        vertices.push(Vertex{
+            position:position,
+            uv:uv,
+            normal:normal,
+            tangent:tangent,
+        });
+    }
+    vertices
+}
+
+/// Loads every object of an OBJ file, with one model per object. Only an unreadable or invalid OBJ file is an error,
+/// problems with materials, textures and missing attributes are worked around and returned as warnings.
+pub fn load_obj(path: &str,options:&LoadOptions,cache:&mut TextureCache) -> Result<LoadedModels,LoadError>{
     //get path of the directory
-    let directory = path::Path::new(path).parent().unwrap();
+    let directory = path::Path::new(path).parent().unwrap_or(path::Path::new(""));
 
     let mut loaded_models = Vec::<Model>::new();
+    let mut warnings = Vec::<LoadError>::new();
 
     let(models,materials) = 
         tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)
-        .expect("Failed to OBJ load file");
+        .map_err(|e| tobj_error(path,e))?;
 
+    //a missing or broken material library leaves every mesh with the default material
     let materials = match materials{
         Ok(materials) => materials,
-        Err(e) => {println!("No materials found, {}",e);vec![]},
+        Err(e) => {warnings.push(tobj_error(&format!("material library of {}",path),e));vec![]},
     };
 
+    //materials are loaded the first time a mesh uses them and shared by all the others
+    let statements = if materials.is_empty() {HashMap::new()} else {material_statements(path)};
+    let default_material = Arc::new(Material::default());
+    let mut library:Vec<Option<Arc<Material>>> = vec![None; materials.len()];
+
+    //tobj starts a new mesh at every usemtl, meshes of the same object are merged back into one model
+    let mut names = Vec::<&str>::new();
     for model in models.iter(){
-        
-        let mut vertices = Vec::<Vertex>::new();
-        let mut faces = Vec::<Face>::new();
-
-        let mut material = Material{
-            albedo_texture: Texture::white(),
-            normal_texture: Texture::normal_default(),
-            specular_texture: Texture::black(),
+        if !names.contains(&model.name.as_str()){
+            names.push(&model.name);
+        }
+    }
+
+    for name in names{
+        let mut loaded = Model{
+            name:name.to_string(),
+            vertices:Vec::new(),
+            faces:Vec::new(),
+            materials:Vec::new(),
         };
-        if materials.len()>0{
-            let obj_material = &materials[model.mesh.material_id.unwrap()];
-            let albedo_texture = &obj_material.diffuse_texture;
-            if albedo_texture.len()>0 {
-                material.albedo_texture = Texture::load(&directory.join(albedo_texture).to_str().unwrap()).unwrap();
+        //faces of meshes without normals are kept apart until their normals are generated
+        let mut generated = Vec::<Face>::new();
+        let mut has_uvs = true;
+
+        for model in models.iter().filter(|model| model.name==name){
+            let material = match model.mesh.material_id{
+                Some(id) if id<materials.len() => {
+                    library[id].get_or_insert_with(|| match load_material(directory,&materials[id],statements.get(&materials[id].name),cache,&mut warnings){
+                        Ok(material) => Arc::new(material),
+                        Err(message) => {
+                            warnings.push(LoadError::Parse{path:path.to_string(),message:format!("Material {}: {}",materials[id].name,message)});
+                            default_material.clone()
+                        },
+                    }).clone()
+                },
+                Some(id) if !materials.is_empty() => {
+                    warnings.push(LoadError::Parse{path:path.to_string(),message:format!("Mesh {} uses material {} which does not exist",model.name,id)});
+                    default_material.clone()
+                },
+                _ => default_material.clone(),
+            };
+            let material = loaded.material_index(&material);
+
+            let vertex_count = model.mesh.positions.len()/3;
+            let mesh_has_normals = model.mesh.normals.len()==vertex_count*3;
+            let mesh_has_uvs = model.mesh.texcoords.len()==vertex_count*2;
+            has_uvs &= mesh_has_uvs;
+
+            let offset = loaded.vertices.len();
+            loaded.vertices.extend(obj_vertices(&model.mesh,mesh_has_normals,mesh_has_uvs));
+            let indices:Vec<usize> = model.mesh.indices.iter().map(|i| *i as usize+offset).collect();
+            let faces = build_faces(&loaded.vertices,&indices,material);
+            if mesh_has_normals {
+                loaded.faces.extend(faces);
+            } else {
+                generated.extend(faces);
             }
-            let normal_texture = &obj_material.normal_texture;
-            if normal_texture.len()>0 {
-                material.normal_texture = Texture::load(&directory.join(normal_texture).to_str().unwrap()).unwrap();
+        }
+
+        if !generated.is_empty() {
+            warnings.push(LoadError::MissingAttribute{model:name.to_string(),attribute:"normals".to_string()});
+            generate_normals(&mut loaded.vertices,&mut generated,options.normals);
+            loaded.faces.extend(generated);
+        }
+        if !has_uvs {
+            warnings.push(LoadError::MissingAttribute{model:name.to_string(),attribute:"texture coordinates".to_string()});
+        }
+        generate_tangents(&mut loaded.vertices,&loaded.faces);
+        loaded_models.push(loaded);
+    }
+
+    Ok(LoadedModels{models:loaded_models,warnings})
+
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn texture_statement_clamp(){
+        assert_eq!(parse_texture_statement("-clamp on decal.png"),Ok(("decal.png".to_string(),Some(true))));
+        assert_eq!(parse_texture_statement("-clamp off decal.png"),Ok(("decal.png".to_string(),Some(false))));
+        assert_eq!(parse_texture_statement("decal.png"),Ok(("decal.png".to_string(),None)));
+        assert!(parse_texture_statement("-clamp decal.png").is_err());
+    }
+
+    #[test]
+    fn texture_statement_skips_options(){
+        let statement = "-bm 0.5 -s 2 2 -o 0.1 -mm 0 1 -imfchan r -clamp on -t 1 1 1 bump.png";
+        assert_eq!(parse_texture_statement(statement),Ok(("bump.png".to_string(),Some(true))));
+        assert!(parse_texture_statement("-unknown 1 bump.png").is_err());
+        assert!(parse_texture_statement("-s 2 2").is_err());
+    }
+
+    #[test]
+    fn texture_statement_file_with_spaces(){
+        assert_eq!(parse_texture_statement("-clamp on My Textures/brick wall.png"),Ok(("My Textures/brick wall.png".to_string(),Some(true))));
+        assert_eq!(parse_texture_statement("brick wall.png"),Ok(("brick wall.png".to_string(),None)));
+    }
+
+    #[test]
+    fn sampler_statements(){
+        let mut obj_material = tobj::Material::default();
+        for (key,value) in [("wrap","mirror"),("wrap_v","border"),("border_color","1 0 0"),("filter","nearest"),("anisotropy","4")]{
+            obj_material.unknown_param.insert(key.to_string(),value.to_string());
+        }
+        let sampler = parse_sampler(&obj_material).unwrap();
+        assert_eq!(sampler.wrap_u,WrapMode::MirroredRepeat);
+        assert_eq!(sampler.wrap_v,WrapMode::ClampToBorder);
+        assert_eq!(sampler.border_color,glam::Vec4::new(1.0,0.0,0.0,1.0));
+        assert_eq!(sampler.filter,FilterMode::Nearest);
+        assert_eq!(sampler.max_anisotropy,4);
+
+        obj_material.unknown_param.insert("wrap".to_string(),"sideways".to_string());
+        assert!(parse_sampler(&obj_material).is_err());
+    }
+
+    fn vertex(position:glam::Vec3) -> Vertex{
+        Vertex{position,uv:glam::Vec2::ZERO,normal:glam::Vec3::ZERO,tangent:glam::Vec4::ZERO}
+    }
+
+    /// A cube of side 2 sharing its 8 corners between all faces, wound counter-clockwise from outside
+    fn cube() -> (Vec<Vertex>,Vec<Face>){
+        let vertices:Vec<Vertex> = (0..8).map(|i| vertex(glam::Vec3::new(
+            if i&4!=0 {1.0} else {-1.0},
+            if i&2!=0 {1.0} else {-1.0},
+            if i&1!=0 {1.0} else {-1.0},
+        ))).collect();
+        let quads = [[0,1,3,2],[4,6,7,5],[0,4,5,1],[2,3,7,6],[0,2,6,4],[1,5,7,3]];
+        let mut indices = Vec::new();
+        for [a,b,c,d] in quads{
+            indices.extend([a,b,c,a,c,d]);
+        }
+        let faces = build_faces(&vertices,&indices,0);
+        for face in faces.iter(){
+            let center = face.vertices.iter().fold(glam::Vec3::ZERO,|sum,v| sum+vertices[*v].position)/3.0;
+            assert!(face.normal.dot(center)>0.0,"cube faces must point outwards");
+        }
+        (vertices,faces)
+    }
+
+    #[test]
+    fn normal_mode_parse(){
+        assert_eq!(NormalMode::parse("flat"),Ok(NormalMode::Flat));
+        assert_eq!(NormalMode::parse("Smooth"),Ok(NormalMode::Smooth));
+        assert_eq!(NormalMode::parse("30"),Ok(NormalMode::Crease(30.0f32.to_radians())));
+        assert!(NormalMode::parse("sharp").is_err());
+    }
+
+    #[test]
+    fn smooth_normals_stay_shared(){
+        let (mut vertices,mut faces) = cube();
+        generate_normals(&mut vertices,&mut faces,NormalMode::Smooth);
+        assert_eq!(vertices.len(),8);
+        for vertex in vertices.iter(){
+            assert!(vertex.normal.abs_diff_eq(vertex.position.normalize(),1e-5));
+        }
+    }
+
+    #[test]
+    fn crease_splits_sharp_edges(){
+        let (mut vertices,mut faces) = cube();
+        generate_normals(&mut vertices,&mut faces,NormalMode::Crease(30.0f32.to_radians()));
+        //every corner is split once per side of the cube
+        assert_eq!(vertices.len(),24);
+        for face in faces.iter(){
+            for v in face.vertices{
+                assert!(vertices[v].normal.abs_diff_eq(face.normal,1e-5));
             }
-            let specular_texture = &obj_material.specular_texture;
-            if specular_texture.len()>0 {
-                material.specular_texture = Texture::load(&directory.join(specular_texture).to_str().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn flat_normals_are_per_face(){
+        //a bent quad, whose two halves would be averaged by any other mode
+        let mut vertices = vec![
+            vertex(glam::Vec3::new(0.0,0.0,0.0)),
+            vertex(glam::Vec3::new(1.0,0.0,0.0)),
+            vertex(glam::Vec3::new(1.0,1.0,0.1)),
+            vertex(glam::Vec3::new(0.0,1.0,0.0)),
+        ];
+        let mut faces = build_faces(&vertices,&[0,1,2,0,2,3],0);
+        assert!(faces[0].normal.dot(faces[1].normal)<1.0);
+        generate_normals(&mut vertices,&mut faces,NormalMode::Flat);
+        assert_eq!(vertices.len(),6);
+        for face in faces.iter(){
+            for v in face.vertices{
+                assert!(vertices[v].normal.abs_diff_eq(face.normal,1e-5));
             }
         }
+    }
 
-        for i in 0..model.mesh.positions.len()/3{
-            let position:glam::Vec3 = glam::Vec3::new(
-                model.mesh.positions[i*3],
-                model.mesh.positions[i*3+1],
-                model.mesh.positions[i*3+2],
-            );
-            let normal:glam::Vec3 = glam::Vec3::new(
-                model.mesh.normals[i*3],
-                model.mesh.normals[i*3+1],
-                model.mesh.normals[i*3+2],
-            );
-            let uv:glam::Vec2 = glam::Vec2::new(
-                model.mesh.texcoords[i*2],
-                model.mesh.texcoords[i*2+1],
-            );
-            let tangent = glam::Vec3::ZERO; //tangent and bitangent are calculated while iterating faces
-            let bitangent = glam::Vec3::ZERO;
-
-            vertices.push(Vertex{
-                position:position,
-                uv:uv,
-                normal:normal,
-                tangent:tangent,
-                bitangent:bitangent,
-            });
-        }
-
-        for i in 0..model.mesh.indices.len()/3{
-            let face_vertices = [
-                model.mesh.indices[i*3] as usize,
-                model.mesh.indices[i*3+1] as usize,
-                model.mesh.indices[i*3+2] as usize,
+    #[test]
+    fn zero_area_faces_are_ignored(){
+        for mode in [NormalMode::Flat,NormalMode::Smooth,NormalMode::Crease(30.0f32.to_radians())]{
+            let mut vertices = vec![
+                vertex(glam::Vec3::new(0.0,0.0,0.0)),
+                vertex(glam::Vec3::new(1.0,0.0,0.0)),
+                vertex(glam::Vec3::new(1.0,1.0,0.0)),
+                vertex(glam::Vec3::new(0.0,1.0,0.0)),
+                vertex(glam::Vec3::new(0.5,0.0,0.0)),
             ];
-            //(model.vertices[face.vertices[0]].position.xyz()-model.vertices[face.vertices[1]].position.xyz()).cross(model.vertices[face.vertices[1]].position.xyz()-model.vertices[face.vertices[2]].position.xyz()).normalize();
-            let face_normal = (vertices[face_vertices[2]].position-vertices[face_vertices[0]].position)
-                                    .cross(vertices[face_vertices[1]].position-vertices[face_vertices[0]].position)
-                                    .normalize();
-            
-            let deltapos1 = vertices[face_vertices[1]].position-vertices[face_vertices[0]].position;
-            let deltapos2 = vertices[face_vertices[2]].position-vertices[face_vertices[1]].position;
-            let deltauv1 = vertices[face_vertices[1]].uv-vertices[face_vertices[0]].uv;
-            let deltauv2 = vertices[face_vertices[2]].uv-vertices[face_vertices[1]].uv;
-
-            let r = 1.0/(deltauv1.x*deltauv2.y-deltauv1.y*deltauv2.x);
-            let tangent = (deltapos1*deltauv2.y-deltapos2*deltauv1.y)*r;
-            let bitangent = (deltapos2*deltauv1.x-deltapos1*deltauv2.x)*r;
-
-            for v in face_vertices.iter(){
-                vertices[*v].tangent = tangent;
-                vertices[*v].bitangent = bitangent;
+            //the first face is collinear and shares two corners with the square
+            let mut faces = build_faces(&vertices,&[0,4,1,0,1,2,0,2,3],0);
+            assert_eq!(faces[0].normal,glam::Vec3::ZERO);
+            generate_normals(&mut vertices,&mut faces,mode);
+            assert!(vertices.iter().all(|vertex| vertex.normal.is_finite()));
+            //the corners shared with the square keep its normal, the one only used by the line has none
+            for face in faces.iter(){
+                for v in face.vertices{
+                    if vertices[v].position!=glam::Vec3::new(0.5,0.0,0.0) {
+                        assert!(vertices[v].normal.abs_diff_eq(glam::Vec3::Z,1e-5),"{:?} gives {:?}",mode,vertices[v].normal);
+                    }
+                }
             }
+        }
+    }
 
-            let face = Face{
-                vertices:face_vertices,
-                normal:face_normal,
-                tangent:tangent,
-                bitangent:bitangent,
-                
-            };
-            faces.push(face);
+    /// A unit quad in the xy plane facing +z, with the given uvs at its corners
+    fn quad(uvs:[(f32,f32);4]) -> (Vec<Vertex>,Vec<Face>){
+        let positions = [(0.0,0.0),(1.0,0.0),(1.0,1.0),(0.0,1.0)];
+        let vertices:Vec<Vertex> = positions.iter().zip(uvs).map(|((x,y),(u,v))| Vertex{
+            uv: glam::Vec2::new(u,v),
+            normal: glam::Vec3::Z,
+            ..vertex(glam::Vec3::new(*x,*y,0.0))
+        }).collect();
+        let faces = build_faces(&vertices,&[0,1,2,0,2,3],0);
+        (vertices,faces)
+    }
+
+    #[test]
+    fn tangents_follow_uvs(){
+        let (mut vertices,faces) = quad([(0.0,0.0),(1.0,0.0),(1.0,1.0),(0.0,1.0)]);
+        generate_tangents(&mut vertices,&faces);
+        for vertex in vertices.iter(){
+            assert!(vertex.tangent.abs_diff_eq(glam::Vec4::new(1.0,0.0,0.0,1.0),1e-5));
+            assert!(vertex.bitangent().abs_diff_eq(glam::Vec3::Y,1e-5));
         }
+    }
 
-        loaded_models.push(Model{
-            name:model.name.clone(),
-            vertices: vertices,
-            faces: faces,
-            material: material,
-        });
-    };
+    #[test]
+    fn mirrored_uvs_flip_handedness(){
+        //u decreases along x, the tangent points along -x while v still follows +y
+        let (mut vertices,faces) = quad([(1.0,0.0),(0.0,0.0),(0.0,1.0),(1.0,1.0)]);
+        generate_tangents(&mut vertices,&faces);
+        for vertex in vertices.iter(){
+            assert!(vertex.tangent.abs_diff_eq(glam::Vec4::new(-1.0,0.0,0.0,-1.0),1e-5));
+            assert!(vertex.bitangent().abs_diff_eq(glam::Vec3::Y,1e-5));
+        }
+    }
+
+    #[test]
+    fn degenerate_uvs_give_orthogonal_tangents(){
+        let (mut vertices,faces) = quad([(0.5,0.5); 4]);
+        generate_tangents(&mut vertices,&faces);
+        for vertex in vertices.iter(){
+            let tangent = vertex.tangent.truncate();
+            assert!(vertex.tangent.is_finite());
+            assert!((tangent.length()-1.0).abs()<1e-5);
+            assert!(tangent.dot(vertex.normal).abs()<1e-5);
+            assert!(vertex.tangent.w.abs()==1.0);
+        }
+    }
+
+    #[test]
+    fn tangents_are_orthogonal_to_smoothed_normals(){
+        //a bent strip whose averaged normals differ from the face normals
+        let mut vertices:Vec<Vertex> = (0..8).map(|i| {
+            let (x,y) = ((i/2) as f32,(i%2) as f32);
+            Vertex{uv:glam::Vec2::new(x*0.3,y),..vertex(glam::Vec3::new(x,y,(x*1.3).sin()))}
+        }).collect();
+        let mut indices = Vec::new();
+        for column in 0..3{
+            let i = column*2;
+            indices.extend([i,i+2,i+3,i,i+3,i+1]);
+        }
+        let mut faces = build_faces(&vertices,&indices,0);
+        generate_normals(&mut vertices,&mut faces,NormalMode::Smooth);
+        generate_tangents(&mut vertices,&faces);
+        for vertex in vertices.iter(){
+            let tangent = vertex.tangent.truncate();
+            assert!((tangent.length()-1.0).abs()<1e-5);
+            assert!(tangent.dot(vertex.normal).abs()<1e-5);
+            //u still increases along the tangent
+            assert!(tangent.x>0.0);
+            assert_eq!(vertex.tangent.w,1.0);
+        }
+    }
 
-    Ok(loaded_models)
+    #[test]
+    fn explicit_black_colors_are_kept(){
+        let directory = std::env::temp_dir().join("emyrenderer_black_colors");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("black.mtl"),"newmtl black\nKd 0 0 0\nKa 0 0 0\nKe 1 0 0\nnewmtl unset\nKs 0.5 0.5 0.5\n").unwrap();
+        std::fs::write(directory.join("black.obj"),"mtllib black.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\no a\nusemtl black\nf 1//1 2//1 3//1\no b\nusemtl unset\nf 1//1 2//1 3//1\n").unwrap();
+        let path = directory.join("black.obj");
+        let loaded = load_obj(&path.to_string_lossy(),&LoadOptions::default(),&mut TextureCache::new()).unwrap();
 
+        let black = &loaded.models.iter().find(|model| model.name=="a").unwrap().materials[0];
+        assert_eq!(black.albedo,glam::Vec4::new(0.0,0.0,0.0,1.0));
+        assert_eq!(black.ambient,glam::Vec3::ZERO);
+        assert_eq!(black.emissive,glam::Vec3::X);
+        //tobj reads the missing Kd and Ka as black too
+        let unset = &loaded.models.iter().find(|model| model.name=="b").unwrap().materials[0];
+        assert_eq!(unset.albedo,glam::Vec4::ONE);
+        assert_eq!(unset.ambient,glam::Vec3::ONE);
+    }
 }
@@ -0,0 +1,79 @@
+use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
+use winit::window::Window;
+use std::path::Path;
+use crate::framebuffer::Framebuffer;
+use crate::tonemap::ToneMapping;
+
+/// Something that can consume a finished frame, e.g. a window surface.
+/// Sinks with a low dynamic range apply the tone mapping to the HDR framebuffer.
+pub trait FrameSink{
+    fn present(&mut self,framebuffer:&Framebuffer,tone_mapping:&ToneMapping) -> Result<(),String>;
+}
+
+pub struct PixelsSink{
+    pub pixels: Pixels,
+}
+
+impl PixelsSink{
+    pub fn new(width: u32, height: u32, window:&Window) -> Result<PixelsSink, String> {
+        let window_size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, window);
+
+        match PixelsBuilder::new(width,height,surface_texture).build(){
+            Ok(pixels) => Ok(PixelsSink{pixels}),
+            Err(e) => Err(format!("Failed to initialize frame buffer: {}",e)),
+        }
+    }
+}
+
+impl FrameSink for PixelsSink{
+    fn present(&mut self,framebuffer:&Framebuffer,tone_mapping:&ToneMapping) -> Result<(),String>{
+        self.pixels.get_frame().copy_from_slice(&tone_mapping.to_rgba8(&framebuffer.resolve()));
+        self.pixels.render().map_err(|e| format!("Failed to render frame: {}",e))
+    }
+}
+
+/// Writes frames to an image file, the format is picked from the extension.
+/// `.exr` files store the linear HDR colors as 32 bit float, anything else is tone mapped to 8 bit.
+pub struct ImageSink{
+    pub path: String,
+}
+
+impl ImageSink{
+    pub fn new(path:&str) -> ImageSink{
+        ImageSink{path: path.to_string()}
+    }
+
+    fn is_exr(&self) -> bool{
+        Path::new(&self.path).extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.eq_ignore_ascii_case("exr"))
+            .unwrap_or(false)
+    }
+
+    /// Drops the channels the format of the file cannot store, PGM and PBM are grayscale and PPM or JPEG have no alpha
+    fn convert(&self,image:image::DynamicImage) -> image::DynamicImage{
+        let extension = Path::new(&self.path).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        match extension.as_str(){
+            "pgm" | "pbm" => image::DynamicImage::ImageLuma8(image.to_luma8()),
+            "ppm" | "jpg" | "jpeg" => image::DynamicImage::ImageRgb8(image.to_rgb8()),
+            _ => image,
+        }
+    }
+}
+
+impl FrameSink for ImageSink{
+    fn present(&mut self,framebuffer:&Framebuffer,tone_mapping:&ToneMapping) -> Result<(),String>{
+        let color = framebuffer.resolve();
+        let image = if self.is_exr(){
+            let buffer = image::Rgba32FImage::from_raw(framebuffer.width,framebuffer.height,color)
+                .ok_or("Framebuffer size does not match its dimensions")?;
+            image::DynamicImage::ImageRgba32F(buffer)
+        }else{
+            let buffer = image::RgbaImage::from_raw(framebuffer.width,framebuffer.height,tone_mapping.to_rgba8(&color))
+                .ok_or("Framebuffer size does not match its dimensions")?;
+            self.convert(image::DynamicImage::ImageRgba8(buffer))
+        };
+        image.save(&self.path).map_err(|e| format!("Failed to write {}: {}",self.path,e))
+    }
+}
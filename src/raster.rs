@@ -0,0 +1,410 @@
+use glam::Vec4Swizzles;
+use crate::model::Material;
+use crate::shader::{Shader,interpolate_vertoutput,perspective_correct,VertOutput,GlobalData};
+use crate::draw::interpolate_bc;
+use crate::framebuffer::Framebuffer;
+use crate::render_state::{RenderState,BlendMode};
+
+/// Size in pixels of the square screen tiles that are shaded in parallel
+pub const TILE_SIZE: u32 = 32;
+
+/// Vertex positions are snapped to 1/2^SUBPIXEL_BITS of a pixel before rasterizing
+pub const SUBPIXEL_BITS: u32 = 8;
+pub const SUBPIXEL_SCALE: i64 = 1 << SUBPIXEL_BITS;
+
+pub fn to_fixed(value:f32) -> i64{
+    (value * SUBPIXEL_SCALE as f32).round() as i64
+}
+
+/// Sample positions of the standard multisample patterns, in 1/16 of a pixel from the pixel center
+pub const SAMPLES_1X: [(i64,i64); 1] = [(0,0)];
+pub const SAMPLES_2X: [(i64,i64); 2] = [(4,4),(-4,-4)];
+pub const SAMPLES_4X: [(i64,i64); 4] = [(-2,-6),(6,-2),(-6,2),(2,6)];
+pub const SAMPLES_8X: [(i64,i64); 8] = [(1,-3),(-1,3),(5,1),(-3,-5),(-5,5),(-7,-1),(3,7),(7,-7)];
+pub const MAX_SAMPLES: usize = 8;
+
+pub fn sample_pattern(samples:u32) -> Result<&'static [(i64,i64)],String>{
+    match samples{
+        1 => Ok(&SAMPLES_1X),
+        2 => Ok(&SAMPLES_2X),
+        4 => Ok(&SAMPLES_4X),
+        8 => Ok(&SAMPLES_8X),
+        _ => Err(format!("Unsupported sample count {}, use 1, 2, 4 or 8",samples)),
+    }
+}
+
+/// The edge function w(p) = a*p.x + b*p.y + c of a directed edge, in fixed point coordinates.
+/// It is positive for points on the inside of the triangle.
+#[derive(Clone, Copy)]
+pub struct EdgeFunction{
+    pub a: i64,
+    pub b: i64,
+    pub c: i64,
+    /// Subtracted from w so that pixels exactly on an edge are only drawn for top and left edges
+    pub bias: i64,
+}
+
+impl EdgeFunction{
+    pub fn new(from:(i64,i64),to:(i64,i64)) -> EdgeFunction{
+        let a = from.1 - to.1;
+        let b = to.0 - from.0;
+        let c = -(a*from.0 + b*from.1);
+        //window space has y pointing down, the interior is on the right of the edge
+        let is_top = a==0 && b>0;
+        let is_left = a>0;
+        EdgeFunction{
+            a,
+            b,
+            c,
+            bias: if is_top || is_left {0} else {1},
+        }
+    }
+
+    pub fn evaluate(&self,x:i64,y:i64) -> i64{
+        self.a*x + self.b*y + self.c
+    }
+
+    /// How much w changes when moving one whole pixel along x
+    pub fn step_x(&self) -> i64{
+        self.a*SUBPIXEL_SCALE
+    }
+
+    /// How much w changes when moving one whole pixel along y
+    pub fn step_y(&self) -> i64{
+        self.b*SUBPIXEL_SCALE
+    }
+}
+
+/// A triangle in window space, ready to be rasterized
+pub struct ScreenTriangle<'a>{
+    pub vertices: [VertOutput; 3],
+    pub material: &'a Material,
+    /// The edge opposite to each vertex, so w of edges[i] is the weight of vertices[i]
+    pub edges: [EdgeFunction; 3],
+    /// Twice the area in fixed point, the sum of the three edge functions
+    pub area: i64,
+    /// Inclusive pixel bounds, already clamped to the screen
+    pub min: (u32,u32),
+    pub max: (u32,u32),
+}
+
+impl<'a> ScreenTriangle<'a>{
+    /// Returns None when the triangle is degenerate or outside of a width x height screen
+    pub fn new(vertices:[VertOutput;3],material:&'a Material,width:u32,height:u32) -> Option<ScreenTriangle<'a>>{
+        let mut vertices = vertices;
+        let mut fixed = vertices.map(|v| (to_fixed(v.position.x),to_fixed(v.position.y)));
+        let mut area = EdgeFunction::new(fixed[0],fixed[1]).evaluate(fixed[2].0,fixed[2].1);
+        if area==0 {
+            return None;
+        }
+        //culling already happened, so both windings are rasterized with the same edge orientation
+        if area<0 {
+            vertices.swap(1,2);
+            fixed.swap(1,2);
+            area = -area;
+        }
+
+        let mut min_box = glam::Vec2::splat(f32::INFINITY);
+        let mut max_box = glam::Vec2::splat(f32::NEG_INFINITY);
+        for v in vertices.iter(){
+            min_box = min_box.min(v.position.xy());
+            max_box = max_box.max(v.position.xy());
+        }
+        //conservative bounds, any sample inside of a pixel may be covered
+        let min_box = min_box.floor().max(glam::Vec2::ZERO);
+        let max_box = max_box.floor().min(glam::Vec2::new((width-1) as f32,(height-1) as f32));
+        if min_box.x>max_box.x || min_box.y>max_box.y {
+            return None;
+        }
+        Some(ScreenTriangle{
+            vertices,
+            material,
+            edges: [
+                EdgeFunction::new(fixed[1],fixed[2]),
+                EdgeFunction::new(fixed[2],fixed[0]),
+                EdgeFunction::new(fixed[0],fixed[1]),
+            ],
+            area,
+            min: (min_box.x as u32,min_box.y as u32),
+            max: (max_box.x as u32,max_box.y as u32),
+        })
+    }
+
+    /// Barycentric coordinates from the biased edge function values of a point
+    pub fn barycentric(&self,w:[i64;3]) -> glam::Vec3{
+        //undo the fill rule bias so the weights sum up to the area
+        glam::Vec3::new(
+            (w[0]+self.edges[0].bias) as f32,
+            (w[1]+self.edges[1].bias) as f32,
+            (w[2]+self.edges[2].bias) as f32,
+        )/self.area as f32
+    }
+
+    /// Perspective correct uv at a point given by its edge function values
+    fn uv_at(&self,w:[i64;3]) -> glam::Vec2{
+        let [t0,t1,t2] = &self.vertices;
+        let bc = perspective_correct(t0,t1,t2,&self.barycentric(w));
+        interpolate_bc(t0.uv,t1.uv,t2.uv,&bc)
+    }
+}
+
+fn offset_w(w:&[i64;3],offset:&[i64;3]) -> [i64;3]{
+    [w[0]+offset[0],w[1]+offset[1],w[2]+offset[2]]
+}
+
+fn is_inside(w:&[i64;3]) -> bool{
+    (w[0] | w[1] | w[2])>=0
+}
+
+/// A rectangle of the framebuffer together with the triangles that overlap it.
+/// Tiles own a copy of their pixels, so they can be rasterized independently.
+pub struct Tile{
+    pub x: u32,
+    pub y: u32,
+    pub buffer: Framebuffer,
+    pub triangles: Vec<usize>,
+}
+
+impl Tile{
+    /// Depth tests the covered samples of a pixel given in tile coordinates, then shades it once
+    /// and writes the color to every sample that passed
+    fn shade_pixel(&mut self,x:i32,y:i32,w:&[i64;3],coverage:u32,offsets:&[[i64;3]],triangle:&ScreenTriangle,shader:&dyn Shader,globals:&GlobalData,state:&RenderState){
+        let [t0,t1,t2] = &triangle.vertices;
+        let mut passed = 0u32;
+        let mut depths = [0.0f32; MAX_SAMPLES];
+        for (sample,offset) in offsets.iter().enumerate(){
+            if coverage & (1<<sample) == 0 {
+                continue;
+            }
+            let bc = triangle.barycentric(offset_w(w,offset));
+            let z = bc.x*t0.position.z + bc.y*t1.position.z + bc.z*t2.position.z;
+            if z<self.buffer.get_sample_depth(x,y,sample as u32){
+                passed |= 1<<sample;
+                depths[sample] = z;
+            }
+        }
+        if passed==0 {
+            return;
+        }
+
+        //shade at the pixel center, or at a covered sample when the center is outside the triangle
+        let shading_w = if is_inside(w) {*w} else {offset_w(w,&offsets[passed.trailing_zeros() as usize])};
+        let mut input = interpolate_vertoutput(t0,t1,t2,&triangle.barycentric(shading_w));
+        //uv derivatives by finite differences with the next pixel on each axis
+        let edges = &triangle.edges;
+        input.uv_dx = triangle.uv_at(offset_w(&shading_w,&edges.map(|e| e.step_x())))-input.uv;
+        input.uv_dy = triangle.uv_at(offset_w(&shading_w,&edges.map(|e| e.step_y())))-input.uv;
+        let color = match shader.fragment(&input,triangle.material,globals){
+            Some(color) => color,
+            None => return,
+        };
+        let blend_mode = triangle.material.blend_mode;
+        for (sample,depth) in depths.iter().enumerate().take(offsets.len()){
+            if passed & (1<<sample) == 0 {
+                continue;
+            }
+            let sample = sample as u32;
+            if blend_mode!=BlendMode::Opaque {
+                let blended = blend_mode.blend(color,self.buffer.get_sample(x,y,sample));
+                self.buffer.set_sample(x,y,sample,&blended);
+            } else {
+                self.buffer.set_sample(x,y,sample,&color);
+            }
+            if state.depth_write {
+                self.buffer.set_sample_depth(x,y,sample,*depth);
+            }
+        }
+    }
+
+    pub fn rasterize(&mut self,triangle:&ScreenTriangle,shader:&dyn Shader,globals:&GlobalData,state:&RenderState){
+        let min_x = triangle.min.0.max(self.x);
+        let min_y = triangle.min.1.max(self.y);
+        let max_x = triangle.max.0.min(self.x+self.buffer.width-1);
+        let max_y = triangle.max.1.min(self.y+self.buffer.height-1);
+
+        if min_x>max_x || min_y>max_y {
+            return;
+        }
+
+        let edges = &triangle.edges;
+        //the change of w from the pixel center to each sample
+        let offsets:Vec<[i64;3]> = sample_pattern(self.buffer.samples).unwrap_or(&SAMPLES_1X).iter()
+            .map(|(ox,oy)| edges.map(|e| (e.a*ox + e.b*oy)*SUBPIXEL_SCALE/16))
+            .collect();
+        let half = SUBPIXEL_SCALE/2;
+        let start_x = min_x as i64*SUBPIXEL_SCALE + half;
+        let start_y = min_y as i64*SUBPIXEL_SCALE + half;
+        let mut w_row = edges.map(|e| e.evaluate(start_x,start_y) - e.bias);
+        let step_x = edges.map(|e| e.step_x());
+        let step_y = edges.map(|e| e.step_y());
+
+        for y in min_y..=max_y{
+            let mut w = w_row;
+            for x in min_x..=max_x{
+                let mut coverage = 0u32;
+                for (sample,offset) in offsets.iter().enumerate(){
+                    if is_inside(&offset_w(&w,offset)){
+                        coverage |= 1<<sample;
+                    }
+                }
+                if coverage!=0 {
+                    self.shade_pixel((x-self.x) as i32,(y-self.y) as i32,&w,coverage,&offsets,triangle,shader,globals,state);
+                }
+                for i in 0..3{
+                    w[i] += step_x[i];
+                }
+            }
+            for i in 0..3{
+                w_row[i] += step_y[i];
+            }
+        }
+    }
+}
+
+/// Splits the screen into tiles and assigns every triangle to the tiles its bounding box overlaps,
+/// keeping submission order inside each tile. Tiles that no triangle touches are left out.
+pub fn bin_triangles(triangles:&[ScreenTriangle],framebuffer:&Framebuffer) -> Vec<Tile>{
+    let tiles_x = framebuffer.width.div_ceil(TILE_SIZE);
+    let tiles_y = framebuffer.height.div_ceil(TILE_SIZE);
+    let mut bins = vec![Vec::<usize>::new(); (tiles_x*tiles_y) as usize];
+    for (i,triangle) in triangles.iter().enumerate(){
+        for ty in triangle.min.1/TILE_SIZE..=triangle.max.1/TILE_SIZE{
+            for tx in triangle.min.0/TILE_SIZE..=triangle.max.0/TILE_SIZE{
+                bins[(ty*tiles_x+tx) as usize].push(i);
+            }
+        }
+    }
+
+    let mut tiles = Vec::new();
+    for (i,bin) in bins.into_iter().enumerate(){
+        if bin.is_empty(){
+            continue;
+        }
+        let x = (i as u32%tiles_x)*TILE_SIZE;
+        let y = (i as u32/tiles_x)*TILE_SIZE;
+        let width = TILE_SIZE.min(framebuffer.width-x);
+        let height = TILE_SIZE.min(framebuffer.height-y);
+        tiles.push(Tile{
+            x,
+            y,
+            buffer: framebuffer.region(x,y,width,height),
+            triangles: bin,
+        });
+    }
+    tiles
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::camera::Camera;
+    use crate::shader::{VertInput,generic_vertex};
+    use crate::model::Vertex;
+
+    const SIZE: u32 = 16;
+
+    /// Writes one unit of red per fragment, so with additive blending the red of a pixel counts its writes
+    struct CountShader{}
+    impl Shader for CountShader{
+        fn fragment(&self,_i:&VertOutput,_material:&Material,_globals:&GlobalData) -> Option<glam::Vec4>{
+            Some(glam::Vec4::new(1.0,0.0,0.0,1.0))
+        }
+        fn vertex(&self,vertex:&Vertex,i:&VertInput,_globals:&GlobalData) -> VertOutput{
+            generic_vertex(vertex,i)
+        }
+    }
+
+    fn vertex(x:f32,y:f32) -> VertOutput{
+        VertOutput{
+            position: glam::Vec4::new(x,y,0.5,1.0),
+            world_position: glam::Vec3::ZERO,
+            uv: glam::Vec2::ZERO,
+            normal: glam::Vec3::Z,
+            tangent: glam::Vec3::X,
+            bitangent: glam::Vec3::Y,
+            uv_dx: glam::Vec2::ZERO,
+            uv_dy: glam::Vec2::ZERO,
+        }
+    }
+
+    /// Rasterizes the triangles into a single tile and returns how many times each pixel was written
+    fn write_counts(triangles:&[[(f32,f32);3]]) -> Vec<u32>{
+        let material = Material{blend_mode:BlendMode::Additive,..Material::default()};
+        let globals = GlobalData{
+            ambient_light: glam::Vec3::ZERO,
+            lights: Vec::new(),
+            time: 0.0,
+            camera: Camera::with_fov(SIZE,SIZE,60.0),
+            shadows: Vec::new(),
+            environment: None,
+        };
+        let state = RenderState{depth_write:false,..RenderState::default()};
+        let mut tile = Tile{x:0,y:0,buffer:Framebuffer::new(SIZE,SIZE,1),triangles:Vec::new()};
+        for points in triangles{
+            let triangle = ScreenTriangle::new(points.map(|(x,y)| vertex(x,y)),&material,SIZE,SIZE).expect("triangle should not be degenerate");
+            tile.rasterize(&triangle,&CountShader{},&globals,&state);
+        }
+        let mut counts = Vec::new();
+        for y in 0..SIZE as i32{
+            for x in 0..SIZE as i32{
+                counts.push(tile.buffer.get_pixel(x,y).x as u32);
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn shared_edge_is_drawn_once(){
+        //the edges run through pixel centers, the diagonal shared by both triangles included
+        let counts = write_counts(&[
+            [(0.5,0.5),(8.5,0.5),(8.5,8.5)],
+            [(0.5,0.5),(8.5,8.5),(0.5,8.5)],
+        ]);
+        for y in 0..SIZE{
+            for x in 0..SIZE{
+                let expected = if x<8 && y<8 {1} else {0};
+                assert_eq!(counts[(y*SIZE+x) as usize],expected,"pixel {},{}",x,y);
+            }
+        }
+    }
+
+    #[test]
+    fn fan_is_drawn_once(){
+        //the shared vertex sits on a pixel center, the outer ones at fractional positions
+        let center = (8.5,8.5);
+        let outer:Vec<(f32,f32)> = (0..8).map(|i| {
+            let angle = i as f32*std::f32::consts::TAU/8.0+0.1;
+            (center.0+6.3*angle.cos(),center.1+6.3*angle.sin())
+        }).collect();
+        let triangles:Vec<[(f32,f32);3]> = (0..8).map(|i| [center,outer[i],outer[(i+1)%8]]).collect();
+        let counts = write_counts(&triangles);
+        for y in 0..SIZE{
+            for x in 0..SIZE{
+                let count = counts[(y*SIZE+x) as usize];
+                assert!(count<=1,"pixel {},{} written {} times",x,y,count);
+                let distance = glam::Vec2::new(x as f32+0.5-center.0,y as f32+0.5-center.1).length();
+                if distance<5.0 {
+                    assert_eq!(count,1,"pixel {},{} inside of the fan",x,y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn thin_triangle_is_drawn(){
+        //a sliver less than a tenth of a pixel high that crosses the pixel centers of one row
+        let counts = write_counts(&[[(0.2,2.45),(15.8,2.5),(0.2,2.52)]]);
+        assert!(counts.iter().sum::<u32>()>0);
+        assert!(counts.iter().enumerate().all(|(i,count)| *count==0 || i/SIZE as usize==2));
+    }
+
+    #[test]
+    fn zero_area_triangle_is_rejected(){
+        let material = Material::default();
+        let collinear = [vertex(1.0,1.0),vertex(4.0,4.0),vertex(9.0,9.0)];
+        assert!(ScreenTriangle::new(collinear,&material,SIZE,SIZE).is_none());
+        //distinct points that snap to the same subpixel position
+        let snapped = [vertex(3.0,3.0),vertex(3.0001,3.0),vertex(3.0,3.0001)];
+        assert!(ScreenTriangle::new(snapped,&material,SIZE,SIZE).is_none());
+    }
+}
@@ -0,0 +1,109 @@
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CullMode{
+    None,
+    Back,
+    Front,
+}
+
+/// The winding order, as seen on screen, of triangles that face the camera
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrontFace{
+    CounterClockwise,
+    Clockwise,
+}
+
+impl CullMode{
+    pub fn parse(value:&str) -> Result<CullMode,String>{
+        match value.to_lowercase().as_str(){
+            "none" => Ok(CullMode::None),
+            "back" => Ok(CullMode::Back),
+            "front" => Ok(CullMode::Front),
+            _ => Err(format!("Unknown cull mode {}",value)),
+        }
+    }
+}
+
+impl FrontFace{
+    pub fn parse(value:&str) -> Result<FrontFace,String>{
+        match value.to_lowercase().as_str(){
+            "ccw" => Ok(FrontFace::CounterClockwise),
+            "cw" => Ok(FrontFace::Clockwise),
+            _ => Err(format!("Unknown winding {}",value)),
+        }
+    }
+}
+
+/// How a fragment color is combined with the color already in the framebuffer
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlendMode{
+    Opaque,
+    Alpha,
+    Additive,
+    Multiply,
+    Premultiplied,
+}
+
+impl BlendMode{
+    pub fn parse(value:&str) -> Result<BlendMode,String>{
+        match value.to_lowercase().as_str(){
+            "opaque" => Ok(BlendMode::Opaque),
+            "alpha" => Ok(BlendMode::Alpha),
+            "additive" => Ok(BlendMode::Additive),
+            "multiply" => Ok(BlendMode::Multiply),
+            "premultiplied" => Ok(BlendMode::Premultiplied),
+            _ => Err(format!("Unknown blend mode {}",value)),
+        }
+    }
+
+    /// Transparent materials are drawn after opaque ones, sorted back to front
+    pub fn is_transparent(&self) -> bool{
+        *self!=BlendMode::Opaque
+    }
+
+    pub fn blend(&self,src:glam::Vec4,dst:glam::Vec4) -> glam::Vec4{
+        let src_rgb = src.truncate();
+        let dst_rgb = dst.truncate();
+        let over_alpha = src.w + dst.w*(1.0-src.w);
+        match self{
+            BlendMode::Opaque => src,
+            BlendMode::Alpha => glam::Vec4::from((src_rgb*src.w + dst_rgb*(1.0-src.w),over_alpha)),
+            BlendMode::Premultiplied => glam::Vec4::from((src_rgb + dst_rgb*(1.0-src.w),over_alpha)),
+            BlendMode::Additive => glam::Vec4::from((dst_rgb + src_rgb*src.w,dst.w)),
+            BlendMode::Multiply => glam::Vec4::from((dst_rgb*src_rgb,dst.w)),
+        }
+    }
+}
+
+/// Fixed function state used by the canvas when rasterizing triangles
+#[derive(Clone, Copy, Debug)]
+pub struct RenderState{
+    pub cull_mode: CullMode,
+    pub front_face: FrontFace,
+    pub depth_write: bool,
+}
+
+impl Default for RenderState{
+    fn default() -> RenderState{
+        RenderState{
+            cull_mode: CullMode::None,
+            front_face: FrontFace::CounterClockwise,
+            depth_write: true,
+        }
+    }
+}
+
+impl RenderState{
+    /// Whether a triangle with the given signed area should be skipped. The area is measured
+    /// with y pointing up, so counter clockwise triangles have a positive area.
+    pub fn is_culled(&self,signed_area:f32) -> bool{
+        let is_front = match self.front_face{
+            FrontFace::CounterClockwise => signed_area>0.0,
+            FrontFace::Clockwise => signed_area<0.0,
+        };
+        match self.cull_mode{
+            CullMode::None => false,
+            CullMode::Back => !is_front,
+            CullMode::Front => is_front,
+        }
+    }
+}
@@ -3,81 +3,131 @@ use glam::Vec4Swizzles;
 use crate::model::{Material,Vertex};
 use crate::draw::{interpolate_bc};
 use crate::camera::{Camera};
+use crate::shadow::ShadowMap;
+use crate::environment::{Environment,environment_brdf};
 
 
-pub struct Light{
-    pub position: glam::Vec3,
-    pub color: glam::Vec3,
-    pub intensity: f32,
+pub enum Light{
+    /// A light infinitely far away, like the sun. `direction` is where the light travels towards.
+    Directional{
+        direction: glam::Vec3,
+        color: glam::Vec3,
+        intensity: f32,
+    },
+    /// Inverse square falloff, smoothly reaching zero at `range` when it is set
+    Point{
+        position: glam::Vec3,
+        color: glam::Vec3,
+        intensity: f32,
+        range: Option<f32>,
+    },
+    /// A point light restricted to a cone, fading out between the inner and outer angles (in radians, from the axis)
+    Spot{
+        position: glam::Vec3,
+        direction: glam::Vec3,
+        color: glam::Vec3,
+        intensity: f32,
+        range: Option<f32>,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    /// A rectangle spanning position +- right +- up, emitting on the side of right x up.
+    /// Approximated by the point of the rectangle closest to the shaded point.
+    Area{
+        position: glam::Vec3,
+        right: glam::Vec3,
+        up: glam::Vec3,
+        color: glam::Vec3,
+        intensity: f32,
+    },
 }
 
-pub struct Texture{
-    pub width: u32,
-    pub height: u32,
-    pub pixels: Vec<u8>,
+/// The light arriving at a point
+pub struct LightSample{
+    /// Normalized, from the point towards the light
+    pub direction: glam::Vec3,
+    /// Color times intensity, after falloff
+    pub radiance: glam::Vec3,
 }
 
-impl Texture{
-    pub fn load(path: &str) -> Result<Texture, String> {
-        println!("Loading texture from {}", path);
-
-        let image = image::open(path).expect("Failed to load image");
-        let width = image.width();
-        let height = image.height();
-        let pixels = image.to_rgba8().into_vec();
-
-        Ok(Texture{
-            width,
-            height,
-            pixels,
-        })
-    }
-
-    pub fn white() -> Texture {
-        Texture{
-            width: 1,
-            height: 1,
-            pixels: vec![255, 255, 255, 255],
-        }
-    }
-
-    pub fn black() -> Texture {
-        Texture{
-            width: 1,
-            height: 1,
-            pixels: vec![0, 0, 0, 255],
-        }
+/// Inverse square falloff, windowed so it reaches zero at the range
+fn distance_attenuation(distance:f32,range:Option<f32>) -> f32{
+    let attenuation = 1.0/(distance*distance).max(0.0001);
+    match range{
+        Some(range) => {
+            let ratio = distance/range;
+            let window = (1.0-ratio*ratio*ratio*ratio).clamp(0.0,1.0);
+            attenuation*window*window
+        },
+        None => attenuation,
     }
+}
 
-    pub fn normal_default() -> Texture {
-        Texture{
-            width: 1,
-            height: 1,
-            pixels: vec![128, 128, 255, 255],
+impl Light{
+    pub fn sample(&self,point:glam::Vec3) -> LightSample{
+        match self{
+            Light::Directional{direction,color,intensity} => LightSample{
+                direction: -direction.normalize(),
+                radiance: *color * *intensity,
+            },
+            Light::Point{position,color,intensity,range} => {
+                let dir = *position-point;
+                let distance = dir.length();
+                LightSample{
+                    direction: dir/distance,
+                    radiance: *color * *intensity * distance_attenuation(distance,*range),
+                }
+            },
+            Light::Spot{position,direction,color,intensity,range,inner_angle,outer_angle} => {
+                let dir = *position-point;
+                let distance = dir.length();
+                let light_dir = dir/distance;
+                let cos_outer = outer_angle.cos();
+                let cos_inner = inner_angle.cos().max(cos_outer+0.0001);
+                let t = ((-light_dir).dot(direction.normalize())-cos_outer)/(cos_inner-cos_outer);
+                let cone = t.clamp(0.0,1.0);
+                LightSample{
+                    direction: light_dir,
+                    radiance: *color * *intensity * distance_attenuation(distance,*range) * cone*cone*(3.0-2.0*cone),
+                }
+            },
+            Light::Area{position,right,up,color,intensity} => {
+                let offset = point-*position;
+                let closest = *position
+                    + *right*(offset.dot(*right)/right.length_squared()).clamp(-1.0,1.0)
+                    + *up*(offset.dot(*up)/up.length_squared()).clamp(-1.0,1.0);
+                let dir = closest-point;
+                let distance = dir.length();
+                let light_dir = dir/distance;
+                let facing = (-light_dir).dot(right.cross(*up).normalize()).max(0.0);
+                LightSample{
+                    direction: light_dir,
+                    radiance: *color * *intensity * distance_attenuation(distance,None) * facing,
+                }
+            },
         }
     }
-
-    pub fn get_color(&self,x: u32, y: u32) -> glam::Vec4{
-        let index = (y * self.width + x) as usize;
-        let r = self.pixels[index * 4 + 0] as f32 / 255.0;
-        let g = self.pixels[index * 4 + 1] as f32 / 255.0;
-        let b = self.pixels[index * 4 + 2] as f32 / 255.0;
-        let a = self.pixels[index * 4 + 3] as f32 / 255.0;
-        glam::Vec4::new(r,g,b,a)
-    }
-
-    pub fn get_color_uv(&self,uv:glam::Vec2) -> glam::Vec4{
-        let x = (uv.x.fract() * (self.width-1) as f32) as u32;
-        let y = (((1.0-uv.y).fract()) * (self.height-1) as f32) as u32;
-        return self.get_color(x,y);
-    }
 }
 
 pub struct GlobalData{
     pub ambient_light: glam::Vec3,
     pub lights: Vec<Light>,
     pub time: f32,
-    pub camera: Camera
+    pub camera: Camera,
+    /// Shadow maps in the same order as the lights, empty when shadows are disabled
+    pub shadows: Vec<ShadowMap>,
+    /// Replaces the constant ambient light when set
+    pub environment: Option<Environment>,
+}
+
+impl GlobalData{
+    /// Fraction of a light reaching a point, 1 when the light has no shadow map
+    pub fn shadow(&self,light:usize,position:glam::Vec3,normal:glam::Vec3,light_dir:glam::Vec3) -> f32{
+        match self.shadows.get(light){
+            Some(map) => map.visibility(position,normal,light_dir),
+            None => 1.0,
+        }
+    }
 }
 pub struct VertInput{
     pub mvpv: glam::Mat4,
@@ -86,17 +136,37 @@ pub struct VertInput{
     pub m: glam::Mat4,
     pub mit: glam::Mat4,
 }
+/// Varyings passed from the vertex to the fragment stage. `position` is in clip space out of
+/// the vertex shader, and (x, y, depth, 1/w) in window space once it reaches the fragment shader.
+#[derive(Clone, Copy)]
 pub struct VertOutput{
-    pub position : glam::Vec3,
+    pub position : glam::Vec4,
     pub world_position: glam::Vec3,
     pub uv : glam::Vec2,
     pub normal : glam::Vec3,
     pub tangent : glam::Vec3,
     pub bitangent : glam::Vec3,
+    /// Screen space derivatives of the uv, filled in by the rasterizer to pick texture mip levels
+    pub uv_dx : glam::Vec2,
+    pub uv_dy : glam::Vec2,
 }
 
+/// Turns screen space barycentric coordinates into perspective correct ones, using the 1/w
+/// stored in the w component of window space positions.
+pub fn perspective_correct(a:&VertOutput,b:&VertOutput,c:&VertOutput,barycentric:&glam::Vec3) -> glam::Vec3{
+    let weighted = *barycentric * glam::Vec3::new(a.position.w,b.position.w,c.position.w);
+    let sum = weighted.x + weighted.y + weighted.z;
+    if sum.abs()<f32::EPSILON {
+        return *barycentric;
+    }
+    weighted/sum
+}
+
+/// Interpolates the outputs of a window space triangle. The position is interpolated linearly
+/// in screen space, all other varyings are perspective correct.
 pub fn interpolate_vertoutput(a:&VertOutput,b:&VertOutput,c:&VertOutput,barycentric:&glam::Vec3) -> VertOutput{
     let position = interpolate_bc(a.position,b.position,c.position,barycentric);
+    let barycentric = &perspective_correct(a,b,c,barycentric);
     let world_position = interpolate_bc(a.world_position,b.world_position,c.world_position,barycentric);
     let uv = interpolate_bc(a.uv,b.uv,c.uv,barycentric);
     let normal = interpolate_bc(a.normal,b.normal,c.normal,barycentric);
@@ -108,25 +178,42 @@ pub fn interpolate_vertoutput(a:&VertOutput,b:&VertOutput,c:&VertOutput,barycent
         uv:uv,
         normal:normal,
         tangent:tangent,
-        bitangent:bitangent
+        bitangent:bitangent,
+        uv_dx:glam::Vec2::ZERO,
+        uv_dy:glam::Vec2::ZERO,
+    }
+}
+
+pub fn lerp_vertoutput(a:&VertOutput,b:&VertOutput,t:f32) -> VertOutput{
+    VertOutput{
+        position:a.position.lerp(b.position,t),
+        world_position:a.world_position.lerp(b.world_position,t),
+        uv:a.uv.lerp(b.uv,t),
+        normal:a.normal.lerp(b.normal,t),
+        tangent:a.tangent.lerp(b.tangent,t),
+        bitangent:a.bitangent.lerp(b.bitangent,t),
+        uv_dx:glam::Vec2::ZERO,
+        uv_dy:glam::Vec2::ZERO,
     }
 }
 pub fn reflect(normal:glam::Vec3,direction:glam::Vec3) -> glam::Vec3{
     return direction - 2.0 * normal * (normal.dot(direction));
 }
 
-pub trait Shader{
+pub trait Shader: Sync{
     fn vertex(&self,vertex:&Vertex,i:&VertInput,globals:&GlobalData) -> VertOutput;
-    fn fragment(&self,i:&VertOutput,material:&Material,globals:&GlobalData) -> glam::Vec4;
+    /// Returns None to discard the fragment, leaving color and depth untouched
+    fn fragment(&self,i:&VertOutput,material:&Material,globals:&GlobalData) -> Option<glam::Vec4>;
 }
 
+/// Outputs the position in clip space, the rasterizer takes care of clipping and the perspective divide
 pub fn generic_vertex(vertex:&Vertex,i:&VertInput) -> VertOutput{
-    let om = i.mvpv * glam::Vec4::from((vertex.position,1.0));
-    let position = om.xyz()/om.w;
+    let position = i.mvp * glam::Vec4::from((vertex.position,1.0));
     let world_position = i.m * glam::Vec4::from((vertex.position,1.0));
     let normal = i.mit * glam::Vec4::from((vertex.normal,0.0));
-    let tangent = i.mit * glam::Vec4::from((vertex.tangent,0.0));
-    let bitangent = i.mit * glam::Vec4::from((vertex.bitangent,0.0));
+    //tangents lie on the surface so they follow the model matrix, the bitangent is rebuilt from the handedness
+    let tangent = i.m * glam::Vec4::from((vertex.tangent.truncate(),0.0));
+    let bitangent = glam::Vec4::from((normal.xyz().cross(tangent.xyz())*vertex.tangent.w,0.0));
     let uv = vertex.uv;
     VertOutput{
         position: position,
@@ -135,55 +222,164 @@ pub fn generic_vertex(vertex:&Vertex,i:&VertInput) -> VertOutput{
         normal: normal.xyz(),
         tangent:tangent.xyz(),
         bitangent:bitangent.xyz(),
+        uv_dx:glam::Vec2::ZERO,
+        uv_dy:glam::Vec2::ZERO,
     }
 }
 
 
+/// The interpolated normal perturbed by the normal map of the material
+pub fn mapped_normal(i:&VertOutput,material:&Material) -> glam::Vec3{
+    let tbn = glam::Mat3::from_cols(i.tangent.normalize(), i.bitangent.normalize(), i.normal.normalize());
+    let normal_map = material.sample(&material.normal_texture,i);
+    let normal = (normal_map.xyz() * 2.0 - 1.0).normalize();
+    return (tbn * normal).normalize();
+}
+
 pub struct LitShader{}
 impl Shader for LitShader{
-    fn fragment(&self,i:&VertOutput,material:&Material,globals:&GlobalData) -> glam::Vec4{
-        let tbn = glam::Mat3::from_cols(i.tangent.normalize(), i.bitangent.normalize(), i.normal.normalize());
-        let normal_map = material.normal_texture.get_color_uv(i.uv);
-        let normal = (normal_map.xyz() * 2.0 - 1.0).normalize();
-        let normal = (tbn * normal).normalize();
-
-        let albedo_texture = material.albedo_texture.get_color_uv(i.uv);
-        let mut color = albedo_texture.xyz();
-        let alpha = albedo_texture.w;
+    fn fragment(&self,i:&VertOutput,material:&Material,globals:&GlobalData) -> Option<glam::Vec4>{
+        let normal = mapped_normal(i,material);
+
+        let albedo_texture = material.sample_albedo(i);
+        let diffuse = albedo_texture.xyz();
+        let alpha = material.alpha(i,albedo_texture.w);
+        if material.is_cut_out(alpha){
+            return None;
+        }
         let viewdir = (globals.camera.position-i.world_position).normalize();
 
         let mut light_color = glam::Vec3::new(0.0,0.0,0.0);
         let mut specular_color = glam::Vec3::new(0.0,0.0,0.0);
 
-        let specular_power = material.specular_texture.get_color_uv(i.uv).length() * 256.0;
-        
+        let specular = material.specular * material.sample(&material.specular_texture,i).xyz();
+        let specular_power = (material.shininess*material.sample(&material.shininess_texture,i).x).max(1.0);
 
-        for light in &globals.lights{
-            let dir = light.position - i.world_position;
-            let distance = dir.length();
-            let light_dir = dir.normalize();
+        for (index,light) in globals.lights.iter().enumerate(){
+            let sample = light.sample(i.world_position);
+            let light_dir = sample.direction;
+            let shadow = globals.shadow(index,i.world_position,i.normal.normalize(),light_dir);
 
             let r = reflect(normal,-light_dir);
             let spec = r.dot(viewdir).max(0.0).powf(specular_power);
-            specular_color += spec * light.color * light.intensity;
+            specular_color += spec * sample.radiance * shadow;
 
-            light_color += light.color * light_dir.dot(normal).max(0.0) * (light.intensity / distance*distance);
+            light_color += sample.radiance * light_dir.dot(normal).max(0.0) * shadow;
         }
 
-        color = globals.ambient_light + 
-                color * 
-                (light_color+specular_color);
-        return glam::Vec4::from((color,alpha));
+        let ambient = match &globals.environment{
+            Some(environment) => environment.irradiance(normal)/std::f32::consts::PI,
+            None => globals.ambient_light,
+        }*material.ambient*material.sample(&material.ambient_texture,i).xyz()*diffuse;
+        let emissive = material.emissive*material.sample(&material.emissive_texture,i).xyz();
+        let color = ambient + 
+                diffuse * light_color +
+                specular * specular_color +
+                emissive;
+        return Some(glam::Vec4::from((color,alpha)));
     }
     fn vertex(&self,vertex:&Vertex,i:&VertInput,_globals:&GlobalData) -> VertOutput{
         return generic_vertex(vertex, i);
     }
 }
 
+/// GGX normal distribution, `alpha` is the squared perceptual roughness
+fn distribution_ggx(n_dot_h:f32,alpha:f32) -> f32{
+    let alpha2 = alpha*alpha;
+    let d = n_dot_h*n_dot_h*(alpha2-1.0)+1.0;
+    alpha2/(std::f32::consts::PI*d*d)
+}
+
+/// Smith masking-shadowing with the Schlick-GGX approximation for direct lighting
+fn geometry_smith(n_dot_v:f32,n_dot_l:f32,roughness:f32) -> f32{
+    let k = (roughness+1.0)*(roughness+1.0)/8.0;
+    let g1 = |x:f32| x/(x*(1.0-k)+k);
+    g1(n_dot_v)*g1(n_dot_l)
+}
+
+fn fresnel_schlick(cos_theta:f32,f0:glam::Vec3) -> glam::Vec3{
+    f0 + (glam::Vec3::ONE-f0)*(1.0-cos_theta).clamp(0.0,1.0).powi(5)
+}
+
+/// Cook-Torrance GGX with the metallic-roughness workflow
+pub struct PbrShader{}
+impl Shader for PbrShader{
+    fn fragment(&self,i:&VertOutput,material:&Material,globals:&GlobalData) -> Option<glam::Vec4>{
+        let albedo_texture = material.sample_albedo(i);
+        let albedo = albedo_texture.xyz();
+        let alpha = material.alpha(i,albedo_texture.w);
+        if material.is_cut_out(alpha){
+            return None;
+        }
+        let normal = mapped_normal(i,material);
+        let metallic = (material.metallic*material.sample(&material.metallic_texture,i).x).clamp(0.0,1.0);
+        //very low roughness turns point lights into invisible specks
+        let roughness = (material.roughness*material.sample(&material.roughness_texture,i).x).clamp(0.045,1.0);
+        let occlusion = 1.0+material.occlusion_strength*(material.sample(&material.occlusion_texture,i).x-1.0);
+        let emissive = material.emissive*material.sample(&material.emissive_texture,i).xyz();
+
+        let view_dir = (globals.camera.position-i.world_position).normalize();
+        let n_dot_v = normal.dot(view_dir).max(0.0001);
+        let f0 = glam::Vec3::splat(0.04).lerp(albedo,metallic);
+        let diffuse_color = albedo*(1.0-metallic);
+
+        let mut color = glam::Vec3::ZERO;
+        for (index,light) in globals.lights.iter().enumerate(){
+            let sample = light.sample(i.world_position);
+            let light_dir = sample.direction;
+            let n_dot_l = normal.dot(light_dir);
+            if n_dot_l<=0.0 {
+                continue;
+            }
+            let shadow = globals.shadow(index,i.world_position,i.normal.normalize(),light_dir);
+            let half = (view_dir+light_dir).normalize();
+            let fresnel = fresnel_schlick(half.dot(view_dir),f0);
+            let specular = fresnel*distribution_ggx(normal.dot(half).max(0.0),roughness*roughness)
+                *geometry_smith(n_dot_v,n_dot_l,roughness)/(4.0*n_dot_v*n_dot_l);
+            let diffuse = (glam::Vec3::ONE-fresnel)*diffuse_color/std::f32::consts::PI;
+            color += (diffuse+specular)*sample.radiance*n_dot_l*shadow;
+        }
+
+        match &globals.environment{
+            Some(environment) => {
+                let (scale,bias) = environment_brdf(n_dot_v,roughness);
+                let diffuse = environment.irradiance(normal)/std::f32::consts::PI*diffuse_color;
+                let specular = environment.prefiltered(reflect(normal,-view_dir),roughness)*(f0*scale+bias);
+                color += (diffuse+specular)*occlusion;
+            },
+            None => color += globals.ambient_light*albedo*occlusion,
+        }
+        color += emissive;
+        return Some(glam::Vec4::from((color,alpha)));
+    }
+    fn vertex(&self,vertex:&Vertex,i:&VertInput,_globals:&GlobalData) -> VertOutput{
+        return generic_vertex(vertex,i);
+    }
+}
+
 pub struct UnlitShader{}
 impl Shader for UnlitShader{
-    fn fragment(&self,i:&VertOutput,material:&Material,_globals:&GlobalData) -> glam::Vec4{
-        return material.albedo_texture.get_color_uv(i.uv);
+    fn fragment(&self,i:&VertOutput,material:&Material,_globals:&GlobalData) -> Option<glam::Vec4>{
+        let albedo = material.sample_albedo(i);
+        let alpha = material.alpha(i,albedo.w);
+        if material.is_cut_out(alpha){
+            return None;
+        }
+        return Some(glam::Vec4::from((albedo.xyz(),alpha)));
+    }
+    fn vertex(&self,vertex:&Vertex,i:&VertInput,_globals:&GlobalData) -> VertOutput{
+        return generic_vertex(vertex,i);
+    }
+}
+
+/// Only keeps the depth, used to render shadow maps. Cutout materials still discard their holes.
+pub struct DepthShader{}
+impl Shader for DepthShader{
+    fn fragment(&self,i:&VertOutput,material:&Material,_globals:&GlobalData) -> Option<glam::Vec4>{
+        if material.alpha_cutoff.is_some() && material.is_cut_out(material.alpha(i,material.sample_albedo(i).w)){
+            return None;
+        }
+        return Some(glam::Vec4::ZERO);
     }
     fn vertex(&self,vertex:&Vertex,i:&VertInput,_globals:&GlobalData) -> VertOutput{
         return generic_vertex(vertex,i);
@@ -199,25 +395,107 @@ pub enum DebugMode{
     AlbedoMap,
     SpecularMap,
     NormalMap,
+    AlphaMap,
+    MetallicMap,
+    RoughnessMap,
+    OcclusionMap,
+    EmissiveMap,
 }
 
 pub struct DebugShader{
     pub mode: DebugMode,
 }
 impl Shader for DebugShader{
-    fn fragment(&self,i:&VertOutput,material:&Material,_globals:&GlobalData) -> glam::Vec4{
+    fn fragment(&self,i:&VertOutput,material:&Material,_globals:&GlobalData) -> Option<glam::Vec4>{
         match self.mode{
-            DebugMode::Uv => return glam::Vec4::new(i.uv.x,i.uv.y,0.0,1.0),
-            DebugMode::Normal => return glam::Vec4::from((i.normal,1.0)),
-            DebugMode::Position => return glam::Vec4::from((i.world_position,1.0)),
-            DebugMode::Tangent => return glam::Vec4::from((i.tangent,1.0)),
-            DebugMode::Bitangent => return glam::Vec4::from((i.bitangent,1.0)),
-            DebugMode::AlbedoMap => return material.albedo_texture.get_color_uv(i.uv),
-            DebugMode::SpecularMap => return material.specular_texture.get_color_uv(i.uv),
-            DebugMode::NormalMap => return material.normal_texture.get_color_uv(i.uv),
+            DebugMode::Uv => return Some(glam::Vec4::new(i.uv.x,i.uv.y,0.0,1.0)),
+            DebugMode::Normal => return Some(glam::Vec4::from((i.normal,1.0))),
+            DebugMode::Position => return Some(glam::Vec4::from((i.world_position,1.0))),
+            DebugMode::Tangent => return Some(glam::Vec4::from((i.tangent,1.0))),
+            DebugMode::Bitangent => return Some(glam::Vec4::from((i.bitangent,1.0))),
+            DebugMode::AlbedoMap => return Some(material.sample(&material.albedo_texture,i)),
+            DebugMode::SpecularMap => return Some(material.sample(&material.specular_texture,i)),
+            DebugMode::NormalMap => return Some(material.sample(&material.normal_texture,i)),
+            DebugMode::AlphaMap => return Some(material.sample(&material.alpha_texture,i)),
+            DebugMode::MetallicMap => return Some(material.sample(&material.metallic_texture,i)),
+            DebugMode::RoughnessMap => return Some(material.sample(&material.roughness_texture,i)),
+            DebugMode::OcclusionMap => return Some(material.sample(&material.occlusion_texture,i)),
+            DebugMode::EmissiveMap => return Some(material.sample(&material.emissive_texture,i)),
         }
     }
     fn vertex(&self,vertex:&Vertex,i:&VertInput,_globals:&GlobalData) -> VertOutput{
         return generic_vertex(vertex,i);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::camera::Camera;
+    use crate::draw::Canvas;
+
+    fn clip_vertex(projection:&glam::Mat4,view_position:glam::Vec3,uv:glam::Vec2) -> VertOutput{
+        VertOutput{
+            position: *projection*glam::Vec4::from((view_position,1.0)),
+            world_position: view_position,
+            uv,
+            normal: glam::Vec3::Z,
+            tangent: glam::Vec3::X,
+            bitangent: glam::Vec3::Y,
+            uv_dx: glam::Vec2::ZERO,
+            uv_dy: glam::Vec2::ZERO,
+        }
+    }
+
+    /// Barycentric coordinates of a point in a 2D triangle
+    fn barycentric_2d(a:glam::Vec2,b:glam::Vec2,c:glam::Vec2,p:glam::Vec2) -> glam::Vec3{
+        let area = (b-a).perp_dot(c-a);
+        let u = (c-b).perp_dot(p-b)/area;
+        let v = (a-c).perp_dot(p-c)/area;
+        glam::Vec3::new(u,v,1.0-u-v)
+    }
+
+    #[test]
+    fn varyings_are_perspective_correct(){
+        let canvas = Canvas::new(200,100);
+        let globals = GlobalData{
+            ambient_light: glam::Vec3::ZERO,
+            lights: Vec::new(),
+            time: 0.0,
+            camera: Camera::with_fov(200,100,60.0),
+            shadows: Vec::new(),
+            environment: None,
+        };
+        let projection = globals.camera.projection;
+        //a triangle going from close to the camera to far away, so w differs by a factor of 50
+        let clip = [
+            clip_vertex(&projection,glam::Vec3::new(-0.5,-0.5,-1.0),glam::Vec2::new(0.0,0.0)),
+            clip_vertex(&projection,glam::Vec3::new(0.5,-0.5,-1.0),glam::Vec2::new(1.0,0.0)),
+            clip_vertex(&projection,glam::Vec3::new(0.0,20.0,-50.0),glam::Vec2::new(0.5,1.0)),
+        ];
+        assert!(clip[2].position.w/clip[0].position.w>=50.0);
+        let window = clip.map(|v| canvas.to_window_space(&v,&globals));
+
+        for weights in [glam::Vec3::new(0.2,0.3,0.5),glam::Vec3::new(0.6,0.1,0.3),glam::Vec3::new(0.05,0.05,0.9)]{
+            //the reference point, interpolated in clip space where every varying is linear
+            let expected = VertOutput{
+                position: interpolate_bc(clip[0].position,clip[1].position,clip[2].position,&weights),
+                uv: interpolate_bc(clip[0].uv,clip[1].uv,clip[2].uv,&weights),
+                world_position: interpolate_bc(clip[0].world_position,clip[1].world_position,clip[2].world_position,&weights),
+                ..clip[0]
+            };
+            let screen = canvas.to_window_space(&expected,&globals).position;
+            let screen_weights = barycentric_2d(window[0].position.xy(),window[1].position.xy(),window[2].position.xy(),screen.xy());
+            //the screen space weights differ, interpolating with them directly would be wrong
+            assert!((screen_weights-weights).abs().max_element()>0.01);
+
+            let interpolated = interpolate_vertoutput(&window[0],&window[1],&window[2],&screen_weights);
+            assert!(interpolated.uv.abs_diff_eq(expected.uv,1e-4),"{:?} instead of {:?}",interpolated.uv,expected.uv);
+            assert!(interpolated.world_position.abs_diff_eq(expected.world_position,1e-3));
+            //x, y, depth and 1/w are affine in screen space and stay linearly interpolated
+            assert!(interpolated.position.xy().abs_diff_eq(screen.xy(),1e-3));
+            assert!((interpolated.position.z-screen.z).abs()<1e-5);
+            assert!((interpolated.position.w-screen.w).abs()<1e-5);
+        }
+    }
+}
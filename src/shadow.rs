@@ -0,0 +1,205 @@
+use crate::camera::{Camera,viewport_matrix};
+use crate::draw::Canvas;
+use crate::model::Model;
+use crate::shader::{DepthShader,GlobalData,Light};
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings{
+    /// Width and height in texels of every shadow map face
+    pub resolution: u32,
+    /// Constant depth bias in world units
+    pub bias: f32,
+    /// Bias in texels, scaled by the slope of the receiver as seen from the light
+    pub slope_bias: f32,
+    /// PCF filters a (2*radius+1)^2 block of texels, 0 gives hard shadows
+    pub pcf_radius: i32,
+}
+
+impl Default for ShadowSettings{
+    fn default() -> ShadowSettings{
+        ShadowSettings{
+            resolution: 1024,
+            bias: 0.01,
+            slope_bias: 1.5,
+            pcf_radius: 1,
+        }
+    }
+}
+
+/// One depth image rendered from a light. Depths are stored as linear distances along the view direction.
+pub struct ShadowFace{
+    pub view: glam::Mat4,
+    pub projection: glam::Mat4,
+    pub depth: Vec<f32>,
+    /// Size of a texel in world units at a distance of 1 for perspective faces, at any distance for orthographic ones
+    pub texel_size: f32,
+    pub orthographic: bool,
+}
+
+impl ShadowFace{
+    fn camera(&self,resolution:u32) -> Camera{
+        Camera{
+            view: self.view,
+            projection: self.projection,
+            viewport: viewport_matrix(0.0,0.0,resolution as f32,resolution as f32,1.0),
+            position: self.view.inverse().transform_point3(glam::Vec3::ZERO),
+        }
+    }
+
+    fn texel_world_size(&self,depth:f32) -> f32{
+        if self.orthographic {self.texel_size} else {self.texel_size*depth}
+    }
+}
+
+/// The shadow map of a single light: six cube faces for point lights, one orthographic face for directional lights
+pub struct ShadowMap{
+    pub faces: Vec<ShadowFace>,
+    pub settings: ShadowSettings,
+}
+
+impl ShadowMap{
+    pub fn point(position:glam::Vec3,near:f32,far:f32,settings:ShadowSettings) -> ShadowMap{
+        //direction and up vector of each cube face
+        let axes = [
+            (glam::Vec3::X,-glam::Vec3::Y),
+            (-glam::Vec3::X,-glam::Vec3::Y),
+            (glam::Vec3::Y,glam::Vec3::Z),
+            (-glam::Vec3::Y,-glam::Vec3::Z),
+            (glam::Vec3::Z,-glam::Vec3::Y),
+            (-glam::Vec3::Z,-glam::Vec3::Y),
+        ];
+        let projection = glam::Mat4::perspective_rh(std::f32::consts::FRAC_PI_2,1.0,near,far);
+        let faces = axes.iter().map(|(direction,up)| ShadowFace{
+            view: glam::Mat4::look_at_rh(position,position+*direction,*up),
+            projection,
+            depth: Vec::new(),
+            texel_size: 2.0/settings.resolution as f32,
+            orthographic: false,
+        }).collect();
+        ShadowMap{faces,settings}
+    }
+
+    /// A shadow map for light travelling along `direction`, covering a sphere of `radius` around `center`
+    pub fn directional(direction:glam::Vec3,center:glam::Vec3,radius:f32,settings:ShadowSettings) -> ShadowMap{
+        let direction = direction.normalize();
+        let up = if direction.y.abs()>0.99 {glam::Vec3::Z} else {glam::Vec3::Y};
+        let face = ShadowFace{
+            view: glam::Mat4::look_at_rh(center-direction*radius*2.0,center,up),
+            projection: glam::Mat4::orthographic_rh(-radius,radius,-radius,radius,radius,radius*3.0),
+            depth: Vec::new(),
+            texel_size: radius*2.0/settings.resolution as f32,
+            orthographic: true,
+        };
+        ShadowMap{faces:vec![face],settings}
+    }
+
+    /// Fraction of light reaching a point in [0,1]. `normal` and `light_dir` point away from the surface
+    /// and are used to scale the bias. Points outside of every face are lit.
+    pub fn visibility(&self,position:glam::Vec3,normal:glam::Vec3,light_dir:glam::Vec3) -> f32{
+        let size = self.settings.resolution as i32;
+        for face in self.faces.iter(){
+            let view_position = face.view.transform_point3(position);
+            let clip = face.projection*glam::Vec4::from((view_position,1.0));
+            if clip.w<=0.0 {
+                continue;
+            }
+            let ndc = clip.truncate()/clip.w;
+            if ndc.x.abs()>1.0 || ndc.y.abs()>1.0 {
+                continue;
+            }
+            let depth = -view_position.z;
+            let cos = normal.dot(light_dir).clamp(0.05,1.0);
+            let tan = ((1.0-cos*cos).sqrt()/cos).min(10.0);
+            let bias = self.settings.bias + self.settings.slope_bias*tan*face.texel_world_size(depth);
+
+            let x = ((ndc.x+1.0)*0.5*size as f32) as i32;
+            let y = ((1.0-ndc.y)*0.5*size as f32) as i32;
+            let radius = self.settings.pcf_radius;
+            let mut lit = 0;
+            for dy in -radius..=radius{
+                for dx in -radius..=radius{
+                    let tx = (x+dx).clamp(0,size-1);
+                    let ty = (y+dy).clamp(0,size-1);
+                    if depth-bias <= face.depth[(ty*size+tx) as usize] {
+                        lit += 1;
+                    }
+                }
+            }
+            return lit as f32/((radius*2+1)*(radius*2+1)) as f32;
+        }
+        1.0
+    }
+}
+
+/// Center and radius of a sphere containing every model
+fn scene_bounds(models:&[Model]) -> (glam::Vec3,f32){
+    let mut min = glam::Vec3::splat(f32::INFINITY);
+    let mut max = glam::Vec3::splat(f32::NEG_INFINITY);
+    for vertex in models.iter().flat_map(|model| model.vertices.iter()){
+        min = min.min(vertex.position);
+        max = max.max(vertex.position);
+    }
+    if min.x>max.x {
+        return (glam::Vec3::ZERO,1.0);
+    }
+    ((min+max)*0.5,((max-min).length()*0.5).max(0.001))
+}
+
+/// Renders shadow maps with the software rasterizer, reusing the same depth-only canvas for every face
+pub struct ShadowPass{
+    pub settings: ShadowSettings,
+    canvas: Canvas,
+}
+
+impl ShadowPass{
+    pub fn new(settings:ShadowSettings,threads:usize) -> Result<ShadowPass,String>{
+        if settings.resolution==0 {
+            return Err("Shadow map size must be at least 1".to_string());
+        }
+        if settings.pcf_radius<0 {
+            return Err(format!("Invalid shadow filter radius {}, it must be 0 or more",settings.pcf_radius));
+        }
+        let mut canvas = Canvas::new(settings.resolution,settings.resolution);
+        canvas.set_thread_count(threads)?;
+        Ok(ShadowPass{settings,canvas})
+    }
+
+    fn render_face(&mut self,face:&mut ShadowFace,models:&[Model],time:f32){
+        let globals = GlobalData{
+            ambient_light: glam::Vec3::ZERO,
+            lights: Vec::new(),
+            time,
+            camera: face.camera(self.settings.resolution),
+            shadows: Vec::new(),
+            environment: None,
+        };
+        self.canvas.clear_frame();
+        //transparent faces let light through and do not cast shadows
+        for model in models.iter(){
+            self.canvas.draw_model_faces(model,&|material| !material.blend_mode.is_transparent(),&DepthShader{},&globals,false);
+        }
+        let inverse_projection = face.projection.inverse();
+        face.depth = self.canvas.framebuffer.depth.iter().map(|z| {
+            if z.is_infinite() {
+                return f32::INFINITY;
+            }
+            -inverse_projection.project_point3(glam::Vec3::new(0.0,0.0,*z)).z
+        }).collect();
+    }
+
+    /// Renders one shadow map per light, in the same order as the lights
+    pub fn render(&mut self,models:&[Model],lights:&[Light],time:f32) -> Vec<ShadowMap>{
+        let (center,radius) = scene_bounds(models);
+        lights.iter().map(|light| {
+            //spot and area lights use the cube map of a point light at their position
+            let mut map = match light{
+                Light::Directional{direction,..} => ShadowMap::directional(*direction,center,radius,self.settings),
+                Light::Point{position,..} | Light::Spot{position,..} | Light::Area{position,..} => ShadowMap::point(*position,0.05,100.0,self.settings),
+            };
+            for face in map.faces.iter_mut(){
+                self.render_face(face,models,time);
+            }
+            map
+        }).collect()
+    }
+}
@@ -0,0 +1,390 @@
+use crate::color::{linear_to_byte,encode_color,decode_color};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FilterMode{
+    /// Closest texel of the closest mip level
+    Nearest,
+    /// Bilinear filtering of the closest mip level
+    Bilinear,
+    /// Bilinear filtering of the two closest mip levels, blended together
+    Trilinear,
+}
+
+impl FilterMode{
+    pub fn parse(value:&str) -> Result<FilterMode,String>{
+        match value.to_lowercase().as_str(){
+            "nearest" => Ok(FilterMode::Nearest),
+            "bilinear" => Ok(FilterMode::Bilinear),
+            "trilinear" => Ok(FilterMode::Trilinear),
+            _ => Err(format!("Unknown filter mode {}",value)),
+        }
+    }
+}
+
+/// What happens to texture coordinates outside of [0,1]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WrapMode{
+    Repeat,
+    MirroredRepeat,
+    /// Coordinates stick to the closest edge texel
+    ClampToEdge,
+    /// Coordinates outside of the texture read the border color of the sampler
+    ClampToBorder,
+}
+
+impl WrapMode{
+    pub fn parse(value:&str) -> Result<WrapMode,String>{
+        match value.to_lowercase().as_str(){
+            "repeat" => Ok(WrapMode::Repeat),
+            "mirror" => Ok(WrapMode::MirroredRepeat),
+            "clamp" => Ok(WrapMode::ClampToEdge),
+            "border" => Ok(WrapMode::ClampToBorder),
+            _ => Err(format!("Unknown wrap mode {}",value)),
+        }
+    }
+
+    /// Maps a texel coordinate into [0,size), or None when it falls on the border
+    fn apply(&self,x:i32,size:u32) -> Option<u32>{
+        let size = size as i32;
+        match self{
+            WrapMode::Repeat => Some(x.rem_euclid(size) as u32),
+            WrapMode::MirroredRepeat => {
+                let x = x.rem_euclid(size*2);
+                Some(if x<size {x} else {size*2-1-x} as u32)
+            },
+            WrapMode::ClampToEdge => Some(x.clamp(0,size-1) as u32),
+            WrapMode::ClampToBorder => if x>=0 && x<size {Some(x as u32)} else {None},
+        }
+    }
+}
+
+/// How a texture is read by shaders
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler{
+    pub filter: FilterMode,
+    /// Maximum number of taps along the direction of anisotropy, 1 disables anisotropic filtering
+    pub max_anisotropy: u32,
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+    pub border_color: glam::Vec4,
+}
+
+impl Default for Sampler{
+    fn default() -> Sampler{
+        Sampler{
+            filter: FilterMode::Trilinear,
+            max_anisotropy: 1,
+            wrap_u: WrapMode::Repeat,
+            wrap_v: WrapMode::Repeat,
+            border_color: glam::Vec4::ZERO,
+        }
+    }
+}
+
+pub struct MipLevel{
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// A borrowed mip level, used so the full resolution pixels and the smaller levels share the sampling code
+#[derive(Clone, Copy)]
+struct LevelView<'a>{
+    width: u32,
+    height: u32,
+    pixels: &'a [u8],
+    srgb: bool,
+}
+
+impl<'a> LevelView<'a>{
+    fn texel(&self,x:u32,y:u32) -> &'a [u8]{
+        let index = ((y*self.width+x)*4) as usize;
+        &self.pixels[index..index+4]
+    }
+
+    /// Box filters the level down to half its size
+    fn downsample(&self) -> MipLevel{
+        let width = (self.width/2).max(1);
+        let height = (self.height/2).max(1);
+        let mut pixels = vec![0u8; (width*height*4) as usize];
+        for y in 0..height{
+            for x in 0..width{
+                let x0 = (x*2).min(self.width-1);
+                let x1 = (x*2+1).min(self.width-1);
+                let y0 = (y*2).min(self.height-1);
+                let y1 = (y*2+1).min(self.height-1);
+                let average = (self.decode(self.texel(x0,y0)) + self.decode(self.texel(x1,y0))
+                            + self.decode(self.texel(x0,y1)) + self.decode(self.texel(x1,y1)))/4.0;
+                let index = ((y*width+x)*4) as usize;
+                pixels[index..index+4].copy_from_slice(&self.encode(average));
+            }
+        }
+        MipLevel{width,height,pixels}
+    }
+
+    /// Texel bytes to a linear color
+    fn decode(&self,texel:&[u8]) -> glam::Vec4{
+        if self.srgb {
+            return decode_color(texel);
+        }
+        glam::Vec4::new(texel[0] as f32,texel[1] as f32,texel[2] as f32,texel[3] as f32)/255.0
+    }
+
+    fn encode(&self,color:glam::Vec4) -> [u8;4]{
+        if self.srgb {
+            return encode_color(&color);
+        }
+        color.to_array().map(linear_to_byte)
+    }
+
+    /// Texel with coordinates wrapped by the sampler
+    fn get_color(&self,x:i32,y:i32,sampler:&Sampler) -> glam::Vec4{
+        let (x,y) = match (sampler.wrap_u.apply(x,self.width),sampler.wrap_v.apply(y,self.height)){
+            (Some(x),Some(y)) => (x,y),
+            _ => return sampler.border_color,
+        };
+        self.decode(self.texel(x,y))
+    }
+
+    /// st are texel space coordinates, texel centers are at half integers
+    fn sample_nearest(&self,st:glam::Vec2,sampler:&Sampler) -> glam::Vec4{
+        self.get_color(st.x.floor() as i32,st.y.floor() as i32,sampler)
+    }
+
+    fn sample_bilinear(&self,st:glam::Vec2,sampler:&Sampler) -> glam::Vec4{
+        let st = st-0.5;
+        let base = st.floor();
+        let f = st-base;
+        let (x,y) = (base.x as i32,base.y as i32);
+        let top = self.get_color(x,y,sampler).lerp(self.get_color(x+1,y,sampler),f.x);
+        let bottom = self.get_color(x,y+1,sampler).lerp(self.get_color(x+1,y+1,sampler),f.x);
+        top.lerp(bottom,f.y)
+    }
+}
+
+/// How the color channels of an image are encoded, the same file gives a different texture for each
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ColorSpace{
+    /// Data such as normals or roughness, used as stored
+    Linear,
+    /// Colors encoded in sRGB
+    Srgb,
+}
+
+impl ColorSpace{
+    pub fn apply(&self,texture:Texture) -> Texture{
+        match self{
+            ColorSpace::Linear => texture,
+            ColorSpace::Srgb => texture.into_srgb(),
+        }
+    }
+}
+
+pub struct Texture{
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    /// Mip levels after the full resolution one, each half the size of the previous
+    pub mips: Vec<MipLevel>,
+    /// Color textures store sRGB values, which are decoded to linear when sampled
+    pub srgb: bool,
+}
+
+impl Texture{
+    pub fn new(width:u32,height:u32,pixels:Vec<u8>) -> Texture{
+        let mut texture = Texture{
+            width,
+            height,
+            pixels,
+            mips: Vec::new(),
+            srgb: false,
+        };
+        texture.generate_mips();
+        texture
+    }
+
+    pub fn load(path: &str) -> Result<Texture, String> {
+        println!("Loading texture from {}", path);
+
+        let image = image::open(path).map_err(|e| e.to_string())?;
+        let width = image.width();
+        let height = image.height();
+        let pixels = image.to_rgba8().into_vec();
+
+        Ok(Texture::new(width,height,pixels))
+    }
+
+    pub fn white() -> Texture {
+        Texture::new(1,1,vec![255, 255, 255, 255])
+    }
+
+    pub fn black() -> Texture {
+        Texture::new(1,1,vec![0, 0, 0, 255])
+    }
+
+    pub fn normal_default() -> Texture {
+        Texture::new(1,1,vec![128, 128, 255, 255])
+    }
+
+    /// Rebuilds the mip chain from the full resolution pixels
+    pub fn generate_mips(&mut self){
+        self.mips.clear();
+        let mut level = self.level(0);
+        while level.width>1 || level.height>1 {
+            self.mips.push(level.downsample());
+            level = self.level(self.mips.len());
+        }
+    }
+
+    /// Marks the texture as sRGB encoded, mips are rebuilt so they are averaged in linear space
+    pub fn into_srgb(mut self) -> Texture{
+        self.srgb = true;
+        self.generate_mips();
+        self
+    }
+
+    pub fn level_count(&self) -> usize{
+        self.mips.len()+1
+    }
+
+    fn level(&self,level:usize) -> LevelView<'_>{
+        match level.min(self.mips.len()){
+            0 => LevelView{width:self.width,height:self.height,pixels:&self.pixels,srgb:self.srgb},
+            level => {
+                let mip = &self.mips[level-1];
+                LevelView{width:mip.width,height:mip.height,pixels:&mip.pixels,srgb:self.srgb}
+            },
+        }
+    }
+
+    /// Bytes used by the pixels of every level
+    pub fn memory_size(&self) -> usize{
+        self.pixels.len() + self.mips.iter().map(|mip| mip.pixels.len()).sum::<usize>()
+    }
+
+    /// Whether any texel has an alpha below one
+    pub fn is_translucent(&self) -> bool{
+        self.pixels.chunks_exact(4).any(|p| p[3]<255)
+    }
+
+    /// Linear color of a texel of the full resolution texture
+    pub fn get_color(&self,x: u32, y: u32) -> glam::Vec4{
+        let level = self.level(0);
+        level.decode(level.texel(x,y))
+    }
+
+    /// Nearest sample of the full resolution texture, with repeating coordinates
+    pub fn get_color_uv(&self,uv:glam::Vec2) -> glam::Vec4{
+        let sampler = Sampler{filter:FilterMode::Nearest,..Sampler::default()};
+        return self.sample_level(0,uv,&sampler);
+    }
+
+    fn sample_level(&self,level:usize,uv:glam::Vec2,sampler:&Sampler) -> glam::Vec4{
+        let level = self.level(level);
+        //the first row of the image is at v=1
+        let st = glam::Vec2::new(uv.x,1.0-uv.y)*glam::Vec2::new(level.width as f32,level.height as f32);
+        match sampler.filter{
+            FilterMode::Nearest => level.sample_nearest(st,sampler),
+            _ => level.sample_bilinear(st,sampler),
+        }
+    }
+
+    fn sample_lod(&self,sampler:&Sampler,uv:glam::Vec2,lod:f32) -> glam::Vec4{
+        let lod = lod.max(0.0);
+        match sampler.filter{
+            FilterMode::Trilinear => {
+                let level = lod.floor();
+                let low = self.sample_level(level as usize,uv,sampler);
+                let high = self.sample_level(level as usize+1,uv,sampler);
+                low.lerp(high,lod-level)
+            },
+            _ => self.sample_level(lod.round() as usize,uv,sampler),
+        }
+    }
+
+    /// Filtered sample, the level of detail is picked from the screen space derivatives of the uv
+    pub fn sample(&self,sampler:&Sampler,uv:glam::Vec2,uv_dx:glam::Vec2,uv_dy:glam::Vec2) -> glam::Vec4{
+        let size = glam::Vec2::new(self.width as f32,self.height as f32);
+        let dx = uv_dx*size;
+        let dy = uv_dy*size;
+        let length_x = dx.length();
+        let length_y = dy.length();
+        let (major,minor,major_axis) = if length_x>=length_y {(length_x,length_y,uv_dx)} else {(length_y,length_x,uv_dy)};
+
+        if sampler.max_anisotropy<=1 || minor<=0.0 {
+            return self.sample_lod(sampler,uv,major.max(f32::MIN_POSITIVE).log2());
+        }
+
+        //take several samples along the major axis of the pixel footprint, each with the detail of the minor one
+        let taps = (major/minor).ceil().min(sampler.max_anisotropy as f32).max(1.0);
+        let lod = (major/taps).max(f32::MIN_POSITIVE).log2();
+        let count = taps as u32;
+        let mut color = glam::Vec4::ZERO;
+        for i in 0..count{
+            let t = (i as f32+0.5)/count as f32-0.5;
+            color += self.sample_lod(sampler,uv+major_axis*t,lod);
+        }
+        color/count as f32
+    }
+}
+
+/// A texture together with the sampler a material reads it with. Textures loaded from files are shared
+/// through the TextureCache.
+pub struct TextureBinding{
+    pub texture: Arc<Texture>,
+    pub sampler: Sampler,
+    /// Reads a single channel, copied to all four, for textures that pack several values like glTF metallic-roughness
+    pub channel: Option<usize>,
+}
+
+impl TextureBinding{
+    pub fn new(texture:Texture) -> TextureBinding{
+        TextureBinding{
+            texture: Arc::new(texture),
+            sampler: Sampler::default(),
+            channel: None,
+        }
+    }
+
+    pub fn sample(&self,uv:glam::Vec2,uv_dx:glam::Vec2,uv_dy:glam::Vec2) -> glam::Vec4{
+        let color = self.texture.sample(&self.sampler,uv,uv_dx,uv_dy);
+        match self.channel{
+            Some(channel) => glam::Vec4::splat(color[channel]),
+            None => color,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn repeat_wraps_around(){
+        let wrapped:Vec<Option<u32>> = [-5,-4,-1,0,3,4,7,9].iter().map(|x| WrapMode::Repeat.apply(*x,4)).collect();
+        assert_eq!(wrapped,[3,0,3,0,3,0,3,1].map(Some).to_vec());
+    }
+
+    #[test]
+    fn mirror_reflects_at_each_edge(){
+        let wrapped:Vec<Option<u32>> = [-5,-4,-1,0,3,4,7,8,9].iter().map(|x| WrapMode::MirroredRepeat.apply(*x,4)).collect();
+        assert_eq!(wrapped,[3,3,0,0,3,3,0,0,1].map(Some).to_vec());
+    }
+
+    #[test]
+    fn wrap_modes_apply_to_uvs(){
+        //one black and one white texel
+        let texture = Texture::new(2,1,vec![0,0,0,255, 255,255,255,255]);
+        let sample = |wrap:WrapMode,u:f32| {
+            let sampler = Sampler{filter:FilterMode::Nearest,wrap_u:wrap,..Sampler::default()};
+            texture.sample_level(0,glam::Vec2::new(u,0.5),&sampler).x
+        };
+        assert_eq!(sample(WrapMode::Repeat,-0.25),1.0);
+        assert_eq!(sample(WrapMode::Repeat,1.25),0.0);
+        assert_eq!(sample(WrapMode::MirroredRepeat,-0.25),0.0);
+        assert_eq!(sample(WrapMode::MirroredRepeat,1.25),1.0);
+        assert_eq!(sample(WrapMode::ClampToEdge,-3.0),0.0);
+        assert_eq!(sample(WrapMode::ClampToEdge,3.0),1.0);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::texture::{Texture,ColorSpace};
+use std::collections::HashMap;
+use std::path::{Path,PathBuf};
+use std::sync::Arc;
+
+/// Identifies a texture built from a file
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct TextureKey{
+    /// Canonical path of the file
+    pub path: PathBuf,
+    /// Index of the image for files that hold several, like glTF
+    pub image: Option<usize>,
+    pub color_space: ColorSpace,
+}
+
+impl TextureKey{
+    /// Key of an image file or of an image inside a file. Fails when the file does not exist.
+    pub fn new(path:&Path,image:Option<usize>,color_space:ColorSpace) -> Result<TextureKey,String>{
+        let path = path.canonicalize().map_err(|e| e.to_string())?;
+        Ok(TextureKey{path,image,color_space})
+    }
+}
+
+/// Textures shared by every material that uses them. Materials hold their own references,
+/// so unloading a texture from the cache only frees it once no material uses it anymore.
+#[derive(Default)]
+pub struct TextureCache{
+    textures: HashMap<TextureKey,Arc<Texture>>,
+}
+
+impl TextureCache{
+    pub fn new() -> TextureCache{
+        TextureCache::default()
+    }
+
+    /// Returns the cached texture for a key, or builds it with `load` and caches it
+    pub fn get_or_load(&mut self,key:TextureKey,load:impl FnOnce() -> Result<Texture,String>) -> Result<Arc<Texture>,String>{
+        if let Some(texture) = self.textures.get(&key){
+            return Ok(texture.clone());
+        }
+        let texture = Arc::new(key.color_space.apply(load()?));
+        self.textures.insert(key,texture.clone());
+        Ok(texture)
+    }
+
+    /// Loads an image file, different paths to the same file share one texture per color space
+    pub fn load(&mut self,path:&Path,color_space:ColorSpace) -> Result<Arc<Texture>,String>{
+        let key = TextureKey::new(path,None,color_space)?;
+        let file = key.path.clone();
+        self.get_or_load(key,|| Texture::load(&file.to_string_lossy()))
+    }
+
+    pub fn len(&self) -> usize{
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.textures.is_empty()
+    }
+
+    /// Bytes used by the pixels of every cached texture
+    pub fn memory_size(&self) -> usize{
+        self.textures.values().map(|texture| texture.memory_size()).sum()
+    }
+
+    /// Removes every texture built from a file, in every color space, returns how many were removed
+    pub fn unload(&mut self,path:&Path) -> usize{
+        let path = path.canonicalize().unwrap_or(path.to_path_buf());
+        let count = self.textures.len();
+        self.textures.retain(|key,_| key.path!=path);
+        count-self.textures.len()
+    }
+
+    /// Removes the textures no material uses anymore, returns how many were removed
+    pub fn unload_unused(&mut self) -> usize{
+        let count = self.textures.len();
+        self.textures.retain(|_,texture| Arc::strong_count(texture)>1);
+        count-self.textures.len()
+    }
+
+    pub fn clear(&mut self){
+        self.textures.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::model::Material;
+    use crate::texture::TextureBinding;
+
+    fn key(path:&str,color_space:ColorSpace) -> TextureKey{
+        TextureKey{path:PathBuf::from(path),image:None,color_space}
+    }
+
+    fn load(cache:&mut TextureCache,key:TextureKey) -> Arc<Texture>{
+        cache.get_or_load(key,|| Ok(Texture::new(4,4,vec![255; 64]))).unwrap()
+    }
+
+    #[test]
+    fn unused_textures_are_unloaded(){
+        let mut cache = TextureCache::new();
+        let atlas = load(&mut cache,key("atlas.png",ColorSpace::Srgb));
+        let kept = load(&mut cache,key("kept.png",ColorSpace::Srgb));
+        let material = Material{albedo_texture:TextureBinding{texture:atlas,sampler:Default::default(),channel:None},..Material::default()};
+        let size = cache.memory_size();
+        assert_eq!(cache.len(),2);
+        //both textures are still used
+        assert_eq!(cache.unload_unused(),0);
+
+        drop(material);
+        assert_eq!(cache.unload_unused(),1);
+        assert_eq!(cache.len(),1);
+        assert_eq!(cache.memory_size(),size-kept.memory_size());
+    }
+
+    #[test]
+    fn unload_removes_every_color_space(){
+        let mut cache = TextureCache::new();
+        let _srgb = load(&mut cache,key("atlas.png",ColorSpace::Srgb));
+        let _linear = load(&mut cache,key("atlas.png",ColorSpace::Linear));
+        let _other = load(&mut cache,key("other.png",ColorSpace::Linear));
+        //a path that does not exist is compared as given
+        assert_eq!(cache.unload(Path::new("atlas.png")),2);
+        assert_eq!(cache.len(),1);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
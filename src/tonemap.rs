@@ -0,0 +1,125 @@
+use crate::color::{linear_to_byte,encode_color};
+
+/// Curve mapping unbounded scene colors into the [0,1] range of the display
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ToneMapOperator{
+    /// Clamps every channel to 1
+    None,
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+impl ToneMapOperator{
+    pub fn parse(value:&str) -> Result<ToneMapOperator,String>{
+        match value.to_lowercase().as_str(){
+            "none" => Ok(ToneMapOperator::None),
+            "reinhard" => Ok(ToneMapOperator::Reinhard),
+            "aces" => Ok(ToneMapOperator::Aces),
+            _ => Err(format!("Unknown tone mapping operator {}",value)),
+        }
+    }
+
+    pub fn apply(&self,x:f32) -> f32{
+        let x = x.max(0.0);
+        match self{
+            ToneMapOperator::None => x.min(1.0),
+            ToneMapOperator::Reinhard => x/(1.0+x),
+            ToneMapOperator::Aces => ((x*(2.51*x+0.03))/(x*(2.43*x+0.59)+0.14)).clamp(0.0,1.0),
+        }
+    }
+
+    /// Scene value that the curve maps to `y`, for y in [0,1). The ACES fit is inverted by solving its quadratic.
+    pub fn invert(&self,y:f32) -> f32{
+        let y = y.clamp(0.0,0.999);
+        match self{
+            ToneMapOperator::None => y,
+            ToneMapOperator::Reinhard => y/(1.0-y),
+            ToneMapOperator::Aces => {
+                let a = 2.51-2.43*y;
+                let b = 0.03-0.59*y;
+                let c = -0.14*y;
+                (-b+(b*b-4.0*a*c).sqrt())/(2.0*a)
+            },
+        }
+    }
+}
+
+/// Post processing that turns the HDR framebuffer into displayable 8 bit colors
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapping{
+    pub operator: ToneMapOperator,
+    /// Exposure in stops, colors are scaled by 2^exposure before the curve
+    pub exposure: f32,
+    /// Output gamma, None encodes with the sRGB transfer function
+    pub gamma: Option<f32>,
+}
+
+impl Default for ToneMapping{
+    fn default() -> ToneMapping{
+        ToneMapping{
+            operator: ToneMapOperator::Aces,
+            exposure: 0.0,
+            gamma: None,
+        }
+    }
+}
+
+impl ToneMapping{
+    /// Tone maps a linear color, the result is still linear and in [0,1]. Alpha is left untouched.
+    pub fn map(&self,color:glam::Vec4) -> glam::Vec4{
+        let scale = self.exposure.exp2();
+        glam::Vec4::new(
+            self.operator.apply(color.x*scale),
+            self.operator.apply(color.y*scale),
+            self.operator.apply(color.z*scale),
+            color.w,
+        )
+    }
+
+    /// HDR color that `map` turns into the given tone mapped color, alpha is left untouched
+    pub fn unmap(&self,color:glam::Vec4) -> glam::Vec4{
+        let scale = (-self.exposure).exp2();
+        glam::Vec4::new(
+            self.operator.invert(color.x)*scale,
+            self.operator.invert(color.y)*scale,
+            self.operator.invert(color.z)*scale,
+            color.w,
+        )
+    }
+
+    /// Tone maps and encodes linear RGBA32F pixels into RGBA8
+    pub fn to_rgba8(&self,pixels:&[f32]) -> Vec<u8>{
+        pixels.chunks_exact(4).flat_map(|pixel| {
+            let color = self.map(glam::Vec4::from_slice(pixel));
+            match self.gamma{
+                Some(gamma) => [
+                    linear_to_byte(color.x.powf(1.0/gamma)),
+                    linear_to_byte(color.y.powf(1.0/gamma)),
+                    linear_to_byte(color.z.powf(1.0/gamma)),
+                    linear_to_byte(color.w),
+                ],
+                None => encode_color(&color),
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn unmap_inverts_map(){
+        for operator in [ToneMapOperator::None,ToneMapOperator::Reinhard,ToneMapOperator::Aces]{
+            for exposure in [-1.0,0.0,1.5]{
+                let tone_mapping = ToneMapping{operator,exposure,gamma:None};
+                for value in [0.0,0.05,0.2158,0.5,0.9]{
+                    let color = glam::Vec4::new(value,value,value,1.0);
+                    let mapped = tone_mapping.map(tone_mapping.unmap(color));
+                    assert!(mapped.abs_diff_eq(color,1e-4),"{:?} at {} gives {:?} for {}",operator,exposure,mapped,value);
+                }
+            }
+        }
+    }
+}